@@ -22,6 +22,10 @@ enum Kind {
         #[structopt(name = "FILE", parse(from_os_str))]
         file: PathBuf,
     },
+    Markdown {
+        #[structopt(name = "FILE", parse(from_os_str))]
+        file: PathBuf,
+    },
 }
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let opts = Opts::from_args();
@@ -32,7 +36,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         Kind::Html { file } => {
             let s = std::fs::read_to_string(file)?;
             match s.parse::<text_tree::content_tree::Node>() {
-                Ok(node) => println!("{}", node),
+                Ok(node) => println!("{:#}", node),
                 Err(e) => eprintln!("parse error: {}", e),
             }
         }
@@ -43,6 +47,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 Err(e) => eprintln!("parse error: {}", e),
             }
         }
+        Kind::Markdown { file } => {
+            let s = std::fs::read_to_string(file)?;
+            println!("{:#}", text_tree::markdown::to_content_tree(&s));
+        }
     }
     Ok(())
 }
@@ -3,7 +3,10 @@ use structopt::StructOpt;
 use tracing_subscriber::EnvFilter;
 
 #[derive(Debug, StructOpt)]
-#[structopt(name = "render", about = "render parsed html styled with parsed tascading style sheets")]
+#[structopt(
+    name = "render",
+    about = "render parsed html styled with parsed tascading style sheets"
+)]
 struct Opts {
     #[structopt(long = "log", env = "RUST_LOG")]
     log: Option<EnvFilter>,
@@ -13,40 +16,42 @@ struct Opts {
 
     #[structopt(name = "tss", parse(from_os_str))]
     tss_file: PathBuf,
+
+    /// The width to lay the document out in.
+    #[structopt(long, default_value = "80")]
+    width: usize,
+
+    /// Shade block boxes to show the layout.
+    #[structopt(long)]
+    debug: bool,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    use text_tree::layout::{Dimensions, Rect, LayoutBox};
+    use text_tree::{Backend, RenderOptions, Renderer};
 
     let opts = Opts::from_args();
     if let Some(log) = opts.log {
         tracing_subscriber::fmt().with_env_filter(log).init();
     }
 
-    let s = std::fs::read_to_string(opts.html_file)?;
-    let html = s.parse::<text_tree::content_tree::Node>()?;
-
-    let s = std::fs::read_to_string(opts.tss_file)?;
-    let stylesheet = s.parse::<text_tree::style::Stylesheet>()?;
-
-    let styled_root = text_tree::style_tree::style_tree(&html, &stylesheet);
-
-    tracing::debug!("styled root: {:#?}", styled_root);
-
-    let mut layout_root = text_tree::layout::build_layout_tree(&styled_root);
-
-    tracing::debug!("layout root: {:#?}", layout_root);
-
-    layout_root.layout(&Dimensions::from_width(80));
-
-    // tracing::debug!("dims: {:#?}", layout_root.dimensions());
-    text_tree::print_boxes(&layout_root);
-
-    let mut c = text_tree::display::DebugCanvas::new(80, 35);
-
-    c.paint(&text_tree::display::build_display_list(&layout_root));
-
-    c.print();
+    let html = std::fs::read_to_string(&opts.html_file)?;
+    let tss = std::fs::read_to_string(opts.tss_file)?;
+
+    let options = RenderOptions {
+        width: opts.width,
+        backend: if opts.debug {
+            Backend::Debug
+        } else {
+            Backend::Text
+        },
+        ..Default::default()
+    };
+    // Linked stylesheets are found relative to the html file.
+    let base = opts.html_file.parent().unwrap_or_else(|| ".".as_ref());
+    let renderer =
+        Renderer::new(&tss, options)?.with_loader(text_tree::style::FileLoader::new(base));
+
+    print!("{}", renderer.render(&html)?);
 
     Ok(())
 }
@@ -0,0 +1,117 @@
+use super::Node;
+
+/// A position in a content tree that remembers how it was reached, so that
+/// its parent and ancestors can be visited.
+#[derive(Debug, Clone)]
+pub struct Cursor<'a> {
+    /// The nodes from the root down to, but not including, the current node.
+    ancestors: Vec<&'a Node>,
+    /// The index of each node on the way down within its parent's children.
+    path: Vec<usize>,
+    node: &'a Node,
+}
+
+impl<'a> Cursor<'a> {
+    pub fn new(root: &'a Node) -> Self {
+        Cursor {
+            ancestors: Vec::new(),
+            path: Vec::new(),
+            node: root,
+        }
+    }
+
+    pub fn node(&self) -> &'a Node {
+        self.node
+    }
+
+    /// The child indices leading from the root to the current node, for use
+    /// with [`Node::node_at`] and [`Node::node_at_mut`].
+    pub fn path(&self) -> Vec<usize> {
+        self.path.clone()
+    }
+
+    /// How many levels below the root the current node is.
+    pub fn depth(&self) -> usize {
+        self.path.len()
+    }
+
+    pub fn parent(&self) -> Option<Cursor<'a>> {
+        let mut ancestors = self.ancestors.clone();
+        let node = ancestors.pop()?;
+        let mut path = self.path.clone();
+        path.pop();
+        Some(Cursor {
+            ancestors,
+            path,
+            node,
+        })
+    }
+
+    /// The current node's ancestors, starting with its parent.
+    pub fn ancestors(&self) -> impl Iterator<Item = &'a Node> + '_ {
+        self.ancestors.iter().rev().copied()
+    }
+
+    pub fn child(&self, index: usize) -> Option<Cursor<'a>> {
+        let node = self.node.children.get(index)?;
+        let mut ancestors = self.ancestors.clone();
+        ancestors.push(self.node);
+        let mut path = self.path.clone();
+        path.push(index);
+        Some(Cursor {
+            ancestors,
+            path,
+            node,
+        })
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = Cursor<'a>> + '_ {
+        (0..self.node.children.len()).filter_map(move |i| self.child(i))
+    }
+
+    pub fn next_sibling(&self) -> Option<Cursor<'a>> {
+        let index = self.path.last()?;
+        self.parent()?.child(index + 1)
+    }
+
+    pub fn previous_sibling(&self) -> Option<Cursor<'a>> {
+        let index = self.path.last()?.checked_sub(1)?;
+        self.parent()?.child(index)
+    }
+
+    /// Cursors at every node below the current one, in document order.
+    pub fn descendants(&self) -> impl Iterator<Item = Cursor<'a>> {
+        let mut stack = self.children().collect::<Vec<_>>();
+        stack.reverse();
+        std::iter::from_fn(move || {
+            let next = stack.pop()?;
+            let first_child = stack.len();
+            stack.extend(next.children());
+            stack[first_child..].reverse();
+            Some(next)
+        })
+    }
+}
+
+/// An iterator over the nodes below a node, in document order.
+pub struct Descendants<'a> {
+    stack: Vec<&'a Node>,
+}
+
+impl<'a> Descendants<'a> {
+    pub(super) fn new(root: &'a Node) -> Self {
+        Descendants {
+            stack: root.children.iter().rev().collect(),
+        }
+    }
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = &'a Node;
+
+    fn next(&mut self) -> Option<&'a Node> {
+        let next = self.stack.pop()?;
+        self.stack.extend(next.children.iter().rev());
+        Some(next)
+    }
+}
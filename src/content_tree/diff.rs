@@ -0,0 +1,395 @@
+//! Finding the changes between two content trees.
+//!
+//! Children are matched up by `id` where they have one, and otherwise by
+//! position among siblings of the same kind, so that a list of keyed items
+//! can be reordered with a few moves instead of being rewritten.
+
+use super::*;
+use std::collections::HashMap;
+
+/// One step in turning a tree into another.
+///
+/// Paths are lists of child indices from the root, as used by
+/// [`Node::node_at`], and refer to the tree as it is when the patch is
+/// applied, after the patches before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Patch {
+    /// Insert `node` as child `index` of the node at `parent`.
+    Insert {
+        parent: Vec<usize>,
+        index: usize,
+        node: Node,
+    },
+    /// Remove child `index` of the node at `parent`.
+    Remove { parent: Vec<usize>, index: usize },
+    /// Move child `from` of the node at `parent` so that it becomes child
+    /// `to`.
+    Move {
+        parent: Vec<usize>,
+        from: usize,
+        to: usize,
+    },
+    /// Replace the node at `path`, used when its kind or tag changes.
+    Replace { path: Vec<usize>, node: Node },
+    /// Change the text of the text node at `path`.
+    SetText { path: Vec<usize>, text: String },
+    /// Set or remove an attribute of the element at `path`.
+    SetAttribute {
+        path: Vec<usize>,
+        name: String,
+        value: Option<String>,
+    },
+}
+
+/// The patches that turn `old` into `new`, in the order they should be
+/// applied.
+pub fn diff(old: &Node, new: &Node) -> Vec<Patch> {
+    let mut patches = Vec::new();
+    diff_node(old, new, &mut Vec::new(), &mut patches);
+    patches
+}
+
+impl Node {
+    /// Apply patches, such as those made by [`diff`], to this tree.
+    pub fn apply(&mut self, patches: &[Patch]) -> Result<(), String> {
+        for patch in patches {
+            let missing = || format!("no node for {:?}", patch);
+            match patch {
+                Patch::Insert {
+                    parent,
+                    index,
+                    node,
+                } => {
+                    let parent = self.node_at_mut(parent).ok_or_else(missing)?;
+                    if *index > parent.children.len() {
+                        return Err(missing());
+                    }
+                    parent.insert_child(*index, node.clone());
+                }
+                Patch::Remove { parent, index } => {
+                    let parent = self.node_at_mut(parent).ok_or_else(missing)?;
+                    if *index >= parent.children.len() {
+                        return Err(missing());
+                    }
+                    parent.remove_child(*index);
+                }
+                Patch::Move { parent, from, to } => {
+                    let parent = self.node_at_mut(parent).ok_or_else(missing)?;
+                    if *from >= parent.children.len() || *to >= parent.children.len() {
+                        return Err(missing());
+                    }
+                    let child = parent.remove_child(*from);
+                    parent.insert_child(*to, child);
+                }
+                Patch::Replace { path, node } => {
+                    *self.node_at_mut(path).ok_or_else(missing)? = node.clone();
+                }
+                Patch::SetText { path, text } => {
+                    match self.node_at_mut(path).ok_or_else(missing)?.node_data {
+                        NodeData::Text(ref mut t) => *t = text.clone(),
+                        NodeData::Element(_) => return Err(missing()),
+                    }
+                }
+                Patch::SetAttribute { path, name, value } => {
+                    self.node_at_mut(path)
+                        .and_then(Node::element_data_mut)
+                        .ok_or_else(missing)?
+                        .set_attribute(name, value.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn diff_node(old: &Node, new: &Node, path: &mut Vec<usize>, patches: &mut Vec<Patch>) {
+    match (&old.node_data, &new.node_data) {
+        (NodeData::Text(a), NodeData::Text(b)) => {
+            if a != b {
+                patches.push(Patch::SetText {
+                    path: path.clone(),
+                    text: b.clone(),
+                });
+            }
+        }
+        (NodeData::Element(a), NodeData::Element(b)) if a.tag_name == b.tag_name => {
+            diff_attributes(a, b, path, patches);
+            diff_children(&old.children, &new.children, path, patches);
+        }
+        _ => patches.push(Patch::Replace {
+            path: path.clone(),
+            node: new.clone(),
+        }),
+    }
+}
+
+fn diff_attributes(old: &ElementData, new: &ElementData, path: &[usize], patches: &mut Vec<Patch>) {
+    let old = effective_attributes(old);
+    let new = effective_attributes(new);
+    for (name, value) in &new {
+        if old.iter().all(|(n, v)| n != name || v != value) {
+            patches.push(Patch::SetAttribute {
+                path: path.to_vec(),
+                name: name.clone(),
+                value: Some(value.clone()),
+            });
+        }
+    }
+    for (name, _) in &old {
+        if new.iter().all(|(n, _)| n != name) {
+            patches.push(Patch::SetAttribute {
+                path: path.to_vec(),
+                name: name.clone(),
+                value: None,
+            });
+        }
+    }
+}
+
+/// An element's attributes, including its id and classes if they weren't
+/// given as attributes, with names in lower case.
+fn effective_attributes(e: &ElementData) -> Vec<(String, String)> {
+    let mut attributes = e
+        .attributes
+        .iter()
+        .map(|a| (a.name.to_ascii_lowercase(), a.value.clone()))
+        .collect::<Vec<_>>();
+    let has = |attributes: &[(String, String)], name| attributes.iter().any(|(n, _)| n == name);
+    if !has(&attributes, "id") {
+        if let Some(ref id) = e.id {
+            attributes.push(("id".to_string(), id.clone()));
+        }
+    }
+    if !has(&attributes, "class") {
+        let mut classes = e
+            .classes
+            .iter()
+            .filter(|c| Some(c.as_str()) != e.tag_name())
+            .cloned()
+            .collect::<Vec<_>>();
+        if !classes.is_empty() {
+            classes.sort();
+            attributes.push(("class".to_string(), classes.join(" ")));
+        }
+    }
+    attributes
+}
+
+/// What must be the same for two children to be matched up.
+#[derive(PartialEq, Eq, Hash)]
+enum Key<'a> {
+    Id(Option<&'a str>, &'a str),
+    Unkeyed(Option<&'a str>),
+    Text,
+}
+
+fn key(node: &Node) -> Key<'_> {
+    match node.node_data {
+        NodeData::Text(_) => Key::Text,
+        NodeData::Element(ref e) => match e.id() {
+            Some(id) => Key::Id(e.tag_name(), id),
+            None => Key::Unkeyed(e.tag_name()),
+        },
+    }
+}
+
+fn diff_children(old: &[Node], new: &[Node], path: &mut Vec<usize>, patches: &mut Vec<Patch>) {
+    // For each new child, the old child it is matched with. Keyed children
+    // match the old child with the same key, and unkeyed children match
+    // the unused old children of the same kind in order.
+    let mut unused = HashMap::<Key, Vec<usize>>::new();
+    for (i, child) in old.iter().enumerate().rev() {
+        unused.entry(key(child)).or_default().push(i);
+    }
+    let matches = new
+        .iter()
+        .map(|child| unused.get_mut(&key(child)).and_then(Vec::pop))
+        .collect::<Vec<_>>();
+
+    // Remove unmatched old children, last first so indices stay valid.
+    let mut matched = vec![false; old.len()];
+    for &i in matches.iter().flatten() {
+        matched[i] = true;
+    }
+    for i in (0..old.len()).rev().filter(|&i| !matched[i]) {
+        patches.push(Patch::Remove {
+            parent: path.clone(),
+            index: i,
+        });
+    }
+
+    // Children in the longest run that is already in order stay where they
+    // are, and everything else is moved or inserted around them.
+    let stay = longest_increasing(&matches);
+    let mut current = (0..old.len())
+        .filter(|&i| matched[i])
+        .map(Some)
+        .collect::<Vec<_>>();
+    let mut target = 0;
+    for (i, (child, &old_index)) in new.iter().zip(&matches).enumerate() {
+        let index = match old_index {
+            Some(old_index) => {
+                let from = current
+                    .iter()
+                    .position(|&c| c == Some(old_index))
+                    .expect("matched children are in the current list");
+                if stay[i] {
+                    from
+                } else {
+                    let to = if from < target { target - 1 } else { target };
+                    current.remove(from);
+                    current.insert(to, Some(old_index));
+                    patches.push(Patch::Move {
+                        parent: path.clone(),
+                        from,
+                        to,
+                    });
+                    to
+                }
+            }
+            None => {
+                current.insert(target, None);
+                patches.push(Patch::Insert {
+                    parent: path.clone(),
+                    index: target,
+                    node: child.clone(),
+                });
+                target
+            }
+        };
+        if let Some(old_index) = old_index {
+            path.push(index);
+            diff_node(&old[old_index], child, path, patches);
+            path.pop();
+        }
+        target = index + 1;
+    }
+}
+
+/// Mark the matched children that form the longest run whose old indices
+/// are increasing.
+fn longest_increasing(matches: &[Option<usize>]) -> Vec<bool> {
+    // `tails[k]` is the position in `matches` of the smallest old index that
+    // ends an increasing run of length `k + 1`.
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous = vec![None; matches.len()];
+    for (i, old_index) in matches.iter().enumerate() {
+        let old_index = match old_index {
+            Some(old_index) => *old_index,
+            None => continue,
+        };
+        let k = tails.partition_point(|&t| matches[t] < Some(old_index));
+        previous[i] = k.checked_sub(1).map(|k| tails[k]);
+        if k == tails.len() {
+            tails.push(i);
+        } else {
+            tails[k] = i;
+        }
+    }
+
+    let mut stay = vec![false; matches.len()];
+    let mut next = tails.last().copied();
+    while let Some(i) = next {
+        stay[i] = true;
+        next = previous[i];
+    }
+    stay
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn check(old: &str, new: &str) -> Vec<Patch> {
+        let old = old.parse::<Node>().unwrap();
+        let new = new.parse::<Node>().unwrap();
+        let patches = diff(&old, &new);
+        let mut patched = old.clone();
+        patched.apply(&patches).unwrap();
+        assert_eq!(patched, new, "patches: {:#?}", patches);
+        patches
+    }
+
+    #[test]
+    fn text_and_attributes() {
+        let patches = check(
+            "<ul><li class=\"a\">one</li><li>two</li></ul>",
+            "<ul><li class=\"b\" title=\"t\">one</li><li>zwei</li></ul>",
+        );
+        assert_eq!(
+            patches,
+            vec![
+                Patch::SetAttribute {
+                    path: vec![0],
+                    name: "class".to_string(),
+                    value: Some("b".to_string()),
+                },
+                Patch::SetAttribute {
+                    path: vec![0],
+                    name: "title".to_string(),
+                    value: Some("t".to_string()),
+                },
+                Patch::SetText {
+                    path: vec![1, 0],
+                    text: "zwei".to_string(),
+                },
+            ]
+        );
+        assert_eq!(check("<a>x</a>", "<a>x</a>"), vec![]);
+    }
+
+    #[test]
+    fn keyed_moves() {
+        // Moving the first item to the end takes a single move.
+        let patches = check(
+            "<ul><li id=\"a\">a</li><li id=\"b\">b</li><li id=\"c\">c</li></ul>",
+            "<ul><li id=\"b\">b</li><li id=\"c\">c</li><li id=\"a\">a!</li></ul>",
+        );
+        assert_eq!(
+            patches,
+            vec![
+                Patch::Move {
+                    parent: vec![],
+                    from: 0,
+                    to: 2,
+                },
+                Patch::SetText {
+                    path: vec![2, 0],
+                    text: "a!".to_string(),
+                },
+            ]
+        );
+
+        let patches = check(
+            "<ul><li id=\"a\">a</li><li id=\"b\">b</li><li id=\"c\">c</li></ul>",
+            "<ul><li id=\"c\">c</li><li id=\"d\">d</li><li id=\"a\">a</li></ul>",
+        );
+        assert_eq!(patches.len(), 3, "patches: {:#?}", patches);
+    }
+
+    #[test]
+    fn inserts_removes_and_replacements() {
+        check(
+            "<div><p>a</p><b>b</b></div>",
+            "<div><b>b</b><i>c</i><p>a</p>d</div>",
+        );
+        check("<div>a<br>b</div>", "<div><p>b</p></div>");
+        check(
+            "<div><p id=\"x\">a</p></div>",
+            "<div><p>a</p><span id=\"x\">a</span></div>",
+        );
+        check("<div><!-- -->text</div>", "<p>text</p>");
+    }
+
+    #[test]
+    fn built_trees() {
+        let old = Node::new(vec![Node::from("a")], Some("x".to_string()), HashSet::new());
+        let mut new = old.clone();
+        new.add_class("c");
+        new.set_text("b");
+        let mut patched = old.clone();
+        patched.apply(&diff(&old, &new)).unwrap();
+        assert_eq!(patched, new);
+    }
+}
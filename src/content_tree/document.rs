@@ -0,0 +1,380 @@
+use super::*;
+use std::ops::Index;
+
+/// A handle to a node in a [`Document`].
+///
+/// Ids stay valid for as long as the document they came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(usize);
+
+/// A content tree stored in an arena, so that nodes can be referred to by
+/// [`NodeId`] and know their parent.
+///
+/// Style and layout trees refer back to a document's nodes by id, instead of
+/// borrowing them.
+///
+/// Changes made through a document's methods, or by applying [`Patch`]es to
+/// it, are numbered by a generation counter, so that style and layout trees
+/// can tell which nodes changed since they were built.
+#[derive(Debug, Clone)]
+pub struct Document {
+    nodes: Vec<DocumentNode>,
+    generation: u64,
+}
+
+/// A node in a [`Document`].
+#[derive(Debug, Clone)]
+pub struct DocumentNode {
+    data: NodeData,
+    span: Span,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    /// The generation in which the node last changed.
+    changed: u64,
+}
+
+impl Document {
+    pub fn new(root: Node) -> Self {
+        let mut document = Document {
+            nodes: Vec::new(),
+            generation: 0,
+        };
+        document.insert(root, None);
+        document
+    }
+
+    /// Add `node` and its descendants to the arena, returning the id of
+    /// `node`.
+    fn insert(&mut self, node: Node, parent: Option<NodeId>) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(DocumentNode {
+            data: node.node_data,
+            span: node.span,
+            parent,
+            children: Vec::new(),
+            changed: self.generation,
+        });
+        let children = node
+            .children
+            .into_iter()
+            .map(|child| self.insert(child, Some(id)))
+            .collect();
+        self.nodes[id.0].children = children;
+        id
+    }
+
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&DocumentNode> {
+        self.nodes.get(id.0)
+    }
+
+    /// The ancestors of a node, starting with its parent.
+    pub fn ancestors(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(self[id].parent, move |&p| self[p].parent)
+    }
+
+    /// The nodes below a node, in document order.
+    pub fn descendants(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        let mut stack = self[id].children.iter().rev().copied().collect::<Vec<_>>();
+        std::iter::from_fn(move || {
+            let next = stack.pop()?;
+            stack.extend(self[next].children.iter().rev());
+            Some(next)
+        })
+    }
+
+    /// The number of changes made to the document so far.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// The ids of nodes in the tree that changed after generation `since`, in
+    /// document order.
+    pub fn changed_since(&self, since: u64) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::once(self.root())
+            .chain(self.descendants(self.root()))
+            .filter(move |&id| self[id].changed > since)
+    }
+
+    /// The node at a path of child indices from the root, as used by
+    /// [`Patch`].
+    fn node_at(&self, path: &[usize]) -> Option<NodeId> {
+        path.iter()
+            .try_fold(self.root(), |id, &i| self[id].children.get(i).copied())
+    }
+
+    /// Replace the text of a text node. Does nothing to an element.
+    pub fn set_text(&mut self, id: NodeId, text: impl Into<String>) {
+        if let NodeData::Text(ref mut t) = self.change(id).data {
+            *t = text.into();
+        }
+    }
+
+    pub fn add_class(&mut self, id: NodeId, class: &str) {
+        if let NodeData::Element(ref mut e) = self.change(id).data {
+            let mut classes = e.classes.clone();
+            classes.insert(class.to_string());
+            e.set_classes(classes);
+        }
+    }
+
+    pub fn remove_class(&mut self, id: NodeId, class: &str) {
+        if let NodeData::Element(ref mut e) = self.change(id).data {
+            let mut classes = e.classes.clone();
+            classes.remove(class);
+            e.set_classes(classes);
+        }
+    }
+
+    /// Set or remove an attribute. Setting `id` or `class` also changes the
+    /// element's id or classes. Does nothing to a text node.
+    pub fn set_attribute(&mut self, id: NodeId, name: &str, value: Option<String>) {
+        if let NodeData::Element(ref mut e) = self.change(id).data {
+            e.set_attribute(name, value);
+        }
+    }
+
+    /// Apply patches, such as those made by [`diff`], to the document.
+    ///
+    /// Inserting, removing, moving or replacing a child changes its parent,
+    /// whose other children may match different selectors as a result.
+    /// Removed nodes are taken out of the tree, but their ids stay valid.
+    pub fn apply(&mut self, patches: &[Patch]) -> Result<(), String> {
+        for patch in patches {
+            let missing = || format!("no node for {:?}", patch);
+            match patch {
+                Patch::Insert {
+                    parent,
+                    index,
+                    node,
+                } => {
+                    let parent = self.node_at(parent).ok_or_else(missing)?;
+                    if *index > self[parent].children.len() {
+                        return Err(missing());
+                    }
+                    self.change(parent);
+                    let child = self.insert(node.clone(), Some(parent));
+                    self.nodes[parent.0].children.insert(*index, child);
+                }
+                Patch::Remove { parent, index } => {
+                    let parent = self.node_at(parent).ok_or_else(missing)?;
+                    if *index >= self[parent].children.len() {
+                        return Err(missing());
+                    }
+                    let child = self.change(parent).children.remove(*index);
+                    self.nodes[child.0].parent = None;
+                }
+                Patch::Move { parent, from, to } => {
+                    let parent = self.node_at(parent).ok_or_else(missing)?;
+                    let len = self[parent].children.len();
+                    if *from >= len || *to >= len {
+                        return Err(missing());
+                    }
+                    let children = &mut self.change(parent).children;
+                    let child = children.remove(*from);
+                    children.insert(*to, child);
+                }
+                Patch::Replace { path, node } => {
+                    let id = self.node_at(path).ok_or_else(missing)?;
+                    self.replace(id, node.clone());
+                }
+                Patch::SetText { path, text } => {
+                    let id = self
+                        .node_at(path)
+                        .filter(|&id| self[id].text().is_some())
+                        .ok_or_else(missing)?;
+                    self.set_text(id, text.clone());
+                }
+                Patch::SetAttribute { path, name, value } => {
+                    let id = self
+                        .node_at(path)
+                        .filter(|&id| self[id].element_data().is_some())
+                        .ok_or_else(missing)?;
+                    self.set_attribute(id, name, value.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace a node's data and descendants with those of `node`, keeping
+    /// its id.
+    fn replace(&mut self, id: NodeId, node: Node) {
+        self.change(self[id].parent.unwrap_or(id));
+        for child in std::mem::take(&mut self.nodes[id.0].children) {
+            self.nodes[child.0].parent = None;
+        }
+        let children = node
+            .children
+            .into_iter()
+            .map(|child| self.insert(child, Some(id)))
+            .collect();
+        let replaced = &mut self.nodes[id.0];
+        replaced.data = node.node_data;
+        replaced.span = node.span;
+        replaced.children = children;
+        replaced.changed = self.generation;
+    }
+
+    /// Start a new generation in which `id` changes.
+    fn change(&mut self, id: NodeId) -> &mut DocumentNode {
+        self.generation += 1;
+        let node = &mut self.nodes[id.0];
+        node.changed = self.generation;
+        node
+    }
+
+    /// Copy a node and its descendants out into a standalone tree.
+    pub fn to_node(&self, id: NodeId) -> Node {
+        let node = &self[id];
+        Node {
+            node_data: node.data.clone(),
+            children: node.children.iter().map(|&c| self.to_node(c)).collect(),
+            span: node.span,
+        }
+    }
+}
+
+impl Index<NodeId> for Document {
+    type Output = DocumentNode;
+
+    fn index(&self, id: NodeId) -> &DocumentNode {
+        &self.nodes[id.0]
+    }
+}
+
+impl From<Node> for Document {
+    fn from(root: Node) -> Self {
+        Document::new(root)
+    }
+}
+
+impl std::str::FromStr for Document {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<Node>().map(Document::new)
+    }
+}
+
+impl DocumentNode {
+    pub fn data(&self) -> &NodeData {
+        &self.data
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        match self.data {
+            NodeData::Text(ref t) => Some(t),
+            NodeData::Element(_) => None,
+        }
+    }
+
+    pub fn element_data(&self) -> Option<&ElementData> {
+        match self.data {
+            NodeData::Element(ref e) => Some(e),
+            NodeData::Text(_) => None,
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    /// The generation in which the node last changed, or 0 if it hasn't.
+    pub fn changed(&self) -> u64 {
+        self.changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn arena() {
+        let html = "<a><b>one</b><c><d>two</d></c></a>";
+        let document = html.parse::<Document>().unwrap();
+        let root = document.root();
+
+        let tags = document
+            .descendants(root)
+            .map(|id| {
+                let node = &document[id];
+                node.element_data()
+                    .and_then(ElementData::tag_name)
+                    .or_else(|| node.text())
+                    .unwrap()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(tags, vec!["b", "one", "c", "d", "two"]);
+
+        let two = document.descendants(root).last().unwrap();
+        assert_eq!(document[two].text(), Some("two"));
+        assert_eq!(document.ancestors(two).count(), 3);
+        assert_eq!(document.ancestors(two).last(), Some(root));
+        assert_eq!(document[root].parent(), None);
+
+        assert_eq!(document.to_node(root), html.parse::<Node>().unwrap());
+    }
+
+    #[test]
+    fn changes() {
+        let mut document = "<a><b>one</b><c>two</c></a>".parse::<Document>().unwrap();
+        let ids = document.descendants(document.root()).collect::<Vec<_>>();
+        assert_eq!(document.generation(), 0);
+
+        document.set_text(ids[1], "uno");
+        document.add_class(ids[2], "x");
+        assert_eq!(document.generation(), 2);
+        assert_eq!(
+            document.changed_since(0).collect::<Vec<_>>(),
+            vec![ids[1], ids[2]]
+        );
+        assert_eq!(document.changed_since(1).collect::<Vec<_>>(), vec![ids[2]]);
+        assert_eq!(
+            document.to_node(document.root()).to_string(),
+            "<a><b>uno</b><c class=\"x\">two</c></a>"
+        );
+
+        document.remove_class(ids[2], "x");
+        document.set_attribute(ids[0], "id", Some("y".to_string()));
+        assert_eq!(
+            document.to_node(document.root()).to_string(),
+            "<a><b id=\"y\">uno</b><c>two</c></a>"
+        );
+    }
+
+    #[test]
+    fn apply() {
+        let old = "<a><b>one</b><c>two</c></a>".parse::<Node>().unwrap();
+        let new = "<a><c>dos</c><d></d></a>".parse::<Node>().unwrap();
+        let mut document = Document::new(old.clone());
+        let root = document.root();
+        let ids = document.descendants(root).collect::<Vec<_>>();
+
+        document.apply(&diff(&old, &new)).unwrap();
+        assert_eq!(document.to_node(root), new);
+        assert!(document.changed_since(0).any(|id| id == root));
+        assert!(document.changed_since(0).all(|id| id != ids[0]));
+        assert_eq!(document[ids[0]].parent(), None);
+
+        let missing = Patch::Remove {
+            parent: vec![5],
+            index: 0,
+        };
+        assert!(document.apply(&[missing]).is_err());
+    }
+}
@@ -1,24 +1,156 @@
+use crate::span::{LineIndex, Span};
+use crate::style::{Selector, Subject};
 use std::collections::HashSet;
 use std::fmt;
+mod cursor;
+mod diff;
+mod document;
 pub mod parse;
+mod serialize;
 
-#[derive(Debug, PartialEq, Eq)]
+pub use cursor::{Cursor, Descendants};
+pub use diff::{diff, Patch};
+pub use document::{Document, DocumentNode, NodeId};
+
+/// Elements whose contents are text up to the matching close tag, with no
+/// markup or character references.
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+/// Elements whose contents are text up to the matching close tag, with
+/// character references but no markup.
+const ESCAPABLE_RAW_TEXT_ELEMENTS: &[&str] = &["textarea", "title"];
+
+#[derive(Debug, Clone, Eq)]
 pub struct Node {
     pub(super) node_data: NodeData,
     pub(super) children: Vec<Node>,
+    pub(super) span: Span,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+// Spans are ignored, so that parsed trees compare equal to the same tree
+// built by hand.
+impl PartialEq for Node {
+    fn eq(&self, other: &Node) -> bool {
+        self.node_data == other.node_data && self.children == other.children
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum NodeData {
     Text(String),
     Element(ElementData),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ElementData {
-    //pub(super) node_type: Option<String>,
+    /// The tag name the element was written with, which is also one of its
+    /// classes. Elements built with [`Node::new`] have no tag name.
+    pub(super) tag_name: Option<String>,
     pub(super) id: Option<String>,
     pub(super) classes: HashSet<String>,
+    /// All attributes in source order, including `id` and `class`.
+    pub(super) attributes: Vec<Attribute>,
+}
+
+#[derive(Debug, Clone, Eq)]
+pub struct Attribute {
+    pub(super) name: String,
+    pub(super) value: String,
+    pub(super) span: Span,
+}
+
+impl PartialEq for Attribute {
+    fn eq(&self, other: &Attribute) -> bool {
+        self.name == other.name && self.value == other.value
+    }
+}
+
+impl ElementData {
+    pub fn tag_name(&self) -> Option<&str> {
+        self.tag_name.as_deref()
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub fn classes(&self) -> &HashSet<String> {
+        &self.classes
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|a| a.name.eq_ignore_ascii_case(name))
+            .map(|a| a.value.as_str())
+    }
+
+    /// Set or remove an attribute, keeping the element's id and classes in
+    /// sync with its `id` and `class` attributes.
+    fn set_attribute(&mut self, name: &str, value: Option<String>) {
+        if name.eq_ignore_ascii_case("id") {
+            self.id = value.clone();
+        } else if name.eq_ignore_ascii_case("class") {
+            self.classes = value
+                .iter()
+                .flat_map(|v| v.split_whitespace())
+                .map(String::from)
+                .chain(self.tag_name.clone())
+                .collect();
+        }
+
+        let existing = self
+            .attributes
+            .iter()
+            .position(|a| a.name.eq_ignore_ascii_case(name));
+        match (existing, value) {
+            (Some(i), Some(value)) => self.attributes[i].value = value,
+            (Some(i), None) => {
+                self.attributes.remove(i);
+            }
+            (None, Some(value)) => self.attributes.push(Attribute {
+                name: name.to_string(),
+                value,
+                span: Span::default(),
+            }),
+            (None, None) => {}
+        }
+    }
+
+    /// Replace the element's classes, rewriting its `class` attribute.
+    ///
+    /// The tag name is always kept as a class, since that is how selectors
+    /// match on it.
+    fn set_classes(&mut self, classes: HashSet<String>) {
+        let mut written = classes
+            .iter()
+            .filter(|c| Some(c.as_str()) != self.tag_name())
+            .cloned()
+            .collect::<Vec<_>>();
+        written.sort();
+        let value = if written.is_empty() {
+            None
+        } else {
+            Some(written.join(" "))
+        };
+        self.set_attribute("class", value);
+        self.classes.extend(classes);
+    }
+}
+
+impl Attribute {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Where this attribute was written in the markup it was parsed from.
+    pub fn span(&self) -> Span {
+        self.span
+    }
 }
 
 impl<T> From<T> for Node
@@ -29,6 +161,7 @@ where
         Node {
             children: Vec::new(),
             node_data: NodeData::Text(String::from(s)),
+            span: Span::default(),
         }
     }
 }
@@ -39,8 +172,8 @@ impl std::str::FromStr for Node {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         use nom::Finish;
         match parse::document(s).finish() {
-            Ok((remaining, node)) => {
-                dbg!(remaining);
+            Ok((_remaining, mut node)) => {
+                node.resolve_spans(&LineIndex::new(s));
                 Ok(node)
             }
             Err(e) => Err(nom::error::convert_error(s, e)),
@@ -48,43 +181,360 @@ impl std::str::FromStr for Node {
     }
 }
 
+/// Parse a fragment of markup, which may contain any number of top-level
+/// elements and text nodes, such as `<b>ok</b> 3 files`.
+///
+/// Unlike parsing a [`Node`], this does not require a single root tag. Any
+/// input that cannot be parsed is reported as an error rather than ignored.
+pub fn parse_fragment(s: &str) -> Result<Vec<Node>, String> {
+    use nom::Finish;
+    match parse::fragment(s).finish() {
+        Ok((_remaining, mut nodes)) => {
+            let index = LineIndex::new(s);
+            for node in &mut nodes {
+                node.resolve_spans(&index);
+            }
+            Ok(nodes)
+        }
+        Err(e) => Err(nom::error::convert_error(s, e)),
+    }
+}
+
+/// Whether `selector` matches the node under `cursor`, which knows where the
+/// node is among its siblings.
+fn matches(cursor: &Cursor, selector: &Selector) -> bool {
+    Subject::at(cursor).is_some_and(|s| selector.matches(&s))
+}
+
 impl Node {
+    /// An element without a tag name. Its `id` and `class` attributes are
+    /// set from `id` and `classes`.
     pub fn new(children: Vec<Node>, id: Option<String>, classes: HashSet<String>) -> Node {
+        let mut element = ElementData {
+            tag_name: None,
+            id: None,
+            classes: HashSet::new(),
+            attributes: Vec::new(),
+        };
+        element.set_attribute("id", id);
+        element.set_classes(classes);
         Node {
             children,
-            node_data: NodeData::Element(ElementData { id, classes }),
+            node_data: NodeData::Element(element),
+            span: Span::default(),
         }
     }
 
+    /// An element with the given tag name, as if it had been parsed from
+    /// markup.
+    pub fn element(tag_name: &str, children: Vec<Node>) -> Node {
+        Node {
+            children,
+            node_data: NodeData::Element(ElementData {
+                tag_name: Some(tag_name.to_string()),
+                id: None,
+                classes: Some(tag_name.to_string()).into_iter().collect(),
+                attributes: Vec::new(),
+            }),
+            span: Span::default(),
+        }
+    }
+
+    /// Parse a fragment of markup (see [`parse_fragment`]) and wrap its
+    /// top-level nodes in an implicit root element with no id or classes.
+    pub fn from_fragment(s: &str) -> Result<Node, String> {
+        Ok(Node::new(parse_fragment(s)?, None, HashSet::new()))
+    }
+
     pub fn text(&self) -> Option<&str> {
         match self.node_data {
             NodeData::Text(ref s) => Some(s),
             NodeData::Element(_) => None,
         }
     }
-}
 
-impl fmt::Display for Node {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fn fmt_at(node: &Node, f: &mut fmt::Formatter<'_>, i: usize) -> fmt::Result {
-            let indent = std::iter::repeat("  ").take(i).collect::<String>(); // todo(eliza): ew lol
-            match node.node_data {
-                NodeData::Text(ref t) => writeln!(f, "{}{:?}", indent, t)?,
-                NodeData::Element(ElementData {
-                    ref id,
-                    ref classes,
-                }) => {
-                    writeln!(f, "{}<tag id={:?} class={:?}>", indent, id, classes)?;
-
-                    for child in &node.children {
-                        fmt_at(child, f, i + 1)?;
-                    }
-
-                    writeln!(f, "{}</tag>", indent)?;
-                }
+    /// Where this node was written in the markup it was parsed from.
+    ///
+    /// For elements, this covers everything from the open tag to the end of
+    /// the close tag.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// This node's attributes in source order, or nothing for a text node.
+    pub fn attributes(&self) -> &[Attribute] {
+        match self.node_data {
+            NodeData::Text(_) => &[],
+            NodeData::Element(ref e) => &e.attributes,
+        }
+    }
+
+    pub fn element_data(&self) -> Option<&ElementData> {
+        match self.node_data {
+            NodeData::Text(_) => None,
+            NodeData::Element(ref e) => Some(e),
+        }
+    }
+
+    fn element_data_mut(&mut self) -> Option<&mut ElementData> {
+        match self.node_data {
+            NodeData::Text(_) => None,
+            NodeData::Element(ref mut e) => Some(e),
+        }
+    }
+
+    pub fn tag_name(&self) -> Option<&str> {
+        self.element_data().and_then(ElementData::tag_name)
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.element_data().and_then(ElementData::id)
+    }
+
+    pub fn has_class(&self, class: &str) -> bool {
+        self.element_data()
+            .is_some_and(|e| e.classes.contains(class))
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.element_data().and_then(|e| e.attribute(name))
+    }
+
+    // == traversal ==========================================================
+
+    pub fn children(&self) -> std::slice::Iter<'_, Node> {
+        self.children.iter()
+    }
+
+    pub fn children_mut(&mut self) -> std::slice::IterMut<'_, Node> {
+        self.children.iter_mut()
+    }
+
+    /// All nodes below this one, in document order.
+    pub fn descendants(&self) -> Descendants<'_> {
+        Descendants::new(self)
+    }
+
+    /// A cursor at this node, which can move around the tree below it while
+    /// keeping track of its ancestors.
+    pub fn cursor(&self) -> Cursor<'_> {
+        Cursor::new(self)
+    }
+
+    /// The node reached by following child indices from this one, as
+    /// returned by [`Cursor::path`].
+    pub fn node_at(&self, path: &[usize]) -> Option<&Node> {
+        path.iter().try_fold(self, |node, &i| node.children.get(i))
+    }
+
+    pub fn node_at_mut(&mut self, path: &[usize]) -> Option<&mut Node> {
+        path.iter()
+            .try_fold(self, |node, &i| node.children.get_mut(i))
+    }
+
+    /// The first descendant, in document order, matched by `selector`.
+    pub fn query_selector(&self, selector: &Selector) -> Option<&Node> {
+        self.cursor()
+            .descendants()
+            .find(|c| matches(c, selector))
+            .map(|c| c.node())
+    }
+
+    /// All descendants matched by `selector`, in document order.
+    pub fn query_selector_all(&self, selector: &Selector) -> Vec<&Node> {
+        self.cursor()
+            .descendants()
+            .filter(|c| matches(c, selector))
+            .map(|c| c.node())
+            .collect()
+    }
+
+    pub fn query_selector_mut(&mut self, selector: &Selector) -> Option<&mut Node> {
+        let path = self
+            .cursor()
+            .descendants()
+            .find(|c| matches(c, selector))?
+            .path();
+        self.node_at_mut(&path)
+    }
+
+    /// Call `f` on every descendant matched by `selector`, in document order.
+    ///
+    /// The matches are found before any of them are modified.
+    pub fn for_each_match_mut(&mut self, selector: &Selector, mut f: impl FnMut(&mut Node)) {
+        let paths = self
+            .cursor()
+            .descendants()
+            .filter(|c| matches(c, selector))
+            .map(|c| c.path())
+            .collect::<Vec<_>>();
+        for path in paths {
+            if let Some(node) = self.node_at_mut(&path) {
+                f(node);
             }
-            Ok(())
         }
-        fmt_at(self, f, 0)
+    }
+
+    // == mutation ===========================================================
+
+    pub fn append_child(&mut self, child: Node) {
+        self.children.push(child);
+    }
+
+    /// Insert a child at `index`, shifting later children along.
+    ///
+    /// Panics if `index` is greater than the number of children.
+    pub fn insert_child(&mut self, index: usize, child: Node) {
+        self.children.insert(index, child);
+    }
+
+    /// Remove and return the child at `index`.
+    ///
+    /// Panics if there is no child at `index`.
+    pub fn remove_child(&mut self, index: usize) -> Node {
+        self.children.remove(index)
+    }
+
+    /// Replace the child at `index`, returning the old child.
+    ///
+    /// Panics if there is no child at `index`.
+    pub fn replace_child(&mut self, index: usize, child: Node) -> Node {
+        std::mem::replace(&mut self.children[index], child)
+    }
+
+    /// Set the text of a text node, or replace all of an element's children
+    /// with a single text node.
+    pub fn set_text(&mut self, text: impl Into<String>) {
+        match self.node_data {
+            NodeData::Text(ref mut t) => *t = text.into(),
+            NodeData::Element(_) => self.children = vec![Node::from(text.into())],
+        }
+    }
+
+    /// Set or remove an element's id. Does nothing to a text node.
+    pub fn set_id(&mut self, id: Option<String>) {
+        if let Some(e) = self.element_data_mut() {
+            e.set_attribute("id", id);
+        }
+    }
+
+    /// Replace an element's classes. Does nothing to a text node.
+    pub fn set_classes(&mut self, classes: HashSet<String>) {
+        if let Some(e) = self.element_data_mut() {
+            e.set_classes(classes);
+        }
+    }
+
+    pub fn add_class(&mut self, class: &str) {
+        if let Some(e) = self.element_data_mut() {
+            let mut classes = e.classes.clone();
+            classes.insert(class.to_string());
+            e.set_classes(classes);
+        }
+    }
+
+    pub fn remove_class(&mut self, class: &str) {
+        if let Some(e) = self.element_data_mut() {
+            let mut classes = e.classes.clone();
+            classes.remove(class);
+            e.set_classes(classes);
+        }
+    }
+
+    /// Set or remove an attribute. Setting `id` or `class` also changes the
+    /// element's id or classes. Does nothing to a text node.
+    pub fn set_attribute(&mut self, name: &str, value: Option<String>) {
+        if let Some(e) = self.element_data_mut() {
+            e.set_attribute(name, value);
+        }
+    }
+
+    fn resolve_spans(&mut self, source: &LineIndex) {
+        self.span.resolve(source);
+        if let NodeData::Element(ref mut e) = self.node_data {
+            for attribute in &mut e.attributes {
+                attribute.span.resolve(source);
+            }
+        }
+        for child in &mut self.children {
+            child.resolve_spans(source);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document() -> Node {
+        "<ul id=\"list\"><li class=\"x\">one</li><li>two <b class=\"x\">2</b></li></ul>"
+            .parse()
+            .expect("it should parse")
+    }
+
+    #[test]
+    fn traversal() {
+        let root = document();
+        let texts = root
+            .descendants()
+            .filter_map(Node::text)
+            .collect::<Vec<_>>();
+        assert_eq!(texts, vec!["one", "two ", "2"]);
+
+        let b = root
+            .cursor()
+            .descendants()
+            .find(|c| c.node().tag_name() == Some("b"))
+            .unwrap();
+        assert_eq!(b.path(), vec![1, 1]);
+        let ancestors = b.ancestors().map(|n| n.tag_name()).collect::<Vec<_>>();
+        assert_eq!(ancestors, vec![Some("li"), Some("ul")]);
+        assert_eq!(b.previous_sibling().unwrap().node().text(), Some("two "));
+        assert!(b.next_sibling().is_none());
+        assert_eq!(b.parent().unwrap().parent().unwrap().node(), &root);
+    }
+
+    #[test]
+    fn query_selectors() {
+        let root = document();
+        let x = ".x".parse::<Selector>().unwrap();
+        let matches = root.query_selector_all(&x);
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[1].tag_name(), Some("b"));
+        assert_eq!(root.query_selector(&x).unwrap().tag_name(), Some("li"));
+        assert!(root.query_selector(&"#nope".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn mutation() {
+        let mut root = document();
+        let x = ".x".parse::<Selector>().unwrap();
+        root.for_each_match_mut(&x, |n| {
+            n.remove_class("x");
+            n.add_class("y");
+            n.add_class("z");
+        });
+        assert!(root.query_selector(&x).is_none());
+        let b = root.node_at(&[1, 1]).unwrap();
+        assert!(b.has_class("b") && b.has_class("y"));
+        assert_eq!(b.attribute("class"), Some("y z"));
+
+        let li = root.query_selector_mut(&".li".parse().unwrap()).unwrap();
+        li.set_id(Some("first".to_string()));
+        li.set_text("uno");
+        assert_eq!(li.attribute("id"), Some("first"));
+
+        let mut item = Node::element("li", vec![Node::from("three")]);
+        item.set_attribute("class", Some("new".to_string()));
+        root.append_child(item);
+        let old = root.replace_child(0, Node::element("li", vec![]));
+        assert_eq!(old.id(), Some("first"));
+        let removed = root.remove_child(1);
+        assert_eq!(removed.children().count(), 2);
+        root.insert_child(0, Node::from("text"));
+
+        let tags = root.children().map(Node::tag_name).collect::<Vec<_>>();
+        assert_eq!(tags, vec![None, Some("li"), Some("li")]);
+        assert!(root.children[2].has_class("new") && root.children[2].has_class("li"));
     }
 }
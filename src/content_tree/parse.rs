@@ -1,5 +1,6 @@
 use super::*;
-use nom::error::{context, VerboseError};
+use crate::span::Span;
+use nom::error::{context, ParseError, VerboseError};
 use nom::{
     branch::*, bytes::complete::*, character::complete::*, combinator::*, multi::*, sequence::*,
 };
@@ -9,13 +10,26 @@ pub type IResult<I, T> = nom::IResult<I, T, VerboseError<I>>;
 
 #[tracing::instrument(level = "trace", err)]
 pub(super) fn document(input: &str) -> IResult<&str, Node> {
-    preceded(
-        opt(delimited(
-            tag_no_case("<!doctype"),
-            take_until(">"),
-            tag(">"),
-        )),
-        skip_ws(context("root tag", html_tag)),
+    terminated(
+        preceded(
+            opt(delimited(
+                tag_no_case("<!doctype"),
+                take_until(">"),
+                tag(">"),
+            )),
+            skip_ws(context("root tag", html_tag)),
+        ),
+        context("end of document", eof),
+    )(input)
+}
+
+/// A fragment of markup with any number of top-level nodes, such as
+/// `<b>ok</b> 3 files`.
+#[tracing::instrument(level = "trace", err)]
+pub(super) fn fragment(input: &str) -> IResult<&str, Vec<Node>> {
+    terminated(
+        many0(preceded(many0(comment), element)),
+        preceded(many0(comment), context("end of fragment", eof)),
     )(input)
 }
 
@@ -50,10 +64,62 @@ pub(super) fn html_tag(input: &str) -> IResult<&str, Node> {
 
 #[tracing::instrument(level = "trace", err)]
 fn text(input: &str) -> IResult<&str, Node> {
-    let (remaining, text) = context("text", preceded(not(tag("<")), take_until("<")))(input)?;
-    let re = regex::Regex::new("\\s+").unwrap();
-    let text = re.replace_all(text.trim_matches('\n'), " ");
-    Ok((remaining, Node::from(text)))
+    // Whitespace is kept verbatim; it is collapsed (or not) during layout,
+    // according to the `white-space` property.
+    let (remaining, text) = context("text", take_till1(|c| c == '<'))(input)?;
+    let mut node = Node::from(decode_entities(text));
+    node.span = Span::consumed(input, remaining);
+    Ok((remaining, node))
+}
+
+/// Replace character references like `&lt;` and `&#x1F970;` with the
+/// characters they stand for. Anything that isn't a known reference is left
+/// as it is.
+fn decode_entities(s: &str) -> String {
+    let mut decoded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let reference = rest[1..]
+            .find(';')
+            .map(|end| &rest[1..end + 1])
+            .and_then(|name| Some((name, decode_entity(name)?)));
+        match reference {
+            Some((name, c)) => {
+                decoded.push(c);
+                rest = &rest[name.len() + 2..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{a0}'),
+        _ => {
+            let number = name.strip_prefix('#')?;
+            let code = match number
+                .strip_prefix('x')
+                .or_else(|| number.strip_prefix('X'))
+            {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse().ok()?,
+            };
+            std::char::from_u32(code)
+        }
+    }
 }
 
 #[tracing::instrument(level = "trace", err)]
@@ -62,7 +128,7 @@ fn identifier(input: &str) -> IResult<&str, &str> {
         "identifier",
         recognize(pair(
             alt((alpha1, tag("_"), tag(":"))),
-            many0(alt((alphanumeric1, tag("_"), tag(":"), tag(".")))),
+            many0(alt((alphanumeric1, tag("_"), tag(":"), tag("."), tag("-")))),
         )),
     )(input)
 }
@@ -76,8 +142,17 @@ fn attribute_value(input: &str) -> IResult<&str, &str> {
 }
 
 #[tracing::instrument(level = "trace", err)]
-fn any_attribute(input: &str) -> IResult<&str, (&str, &str)> {
-    context("attribute", pair(identifier, attribute_value))(input)
+fn any_attribute(input: &str) -> IResult<&str, Attribute> {
+    let (remaining, (name, value)) =
+        context("attribute", pair(identifier, attribute_value))(input)?;
+    Ok((
+        remaining,
+        Attribute {
+            name: name.to_string(),
+            value: decode_entities(value),
+            span: Span::consumed(input, remaining),
+        },
+    ))
 }
 
 /// The contents of an HTML tag, *without* any delimiters.
@@ -88,15 +163,16 @@ fn any_attribute(input: &str) -> IResult<&str, (&str, &str)> {
 fn attrs(input: &str) -> IResult<&str, ElementData> {
     let (remaining, attrs) = opt(preceded(ws1, separated_list0(ws1, any_attribute)))(input)?;
 
+    let attributes = attrs.unwrap_or_default();
     let mut classes = HashSet::new();
     let mut id = None;
-    for (name, val) in attrs.into_iter().flat_map(IntoIterator::into_iter) {
+    for Attribute { name, value, .. } in &attributes {
         match name {
             n if n.eq_ignore_ascii_case("class") => {
-                classes = val.split(' ').map(String::from).collect();
+                classes = value.split(' ').map(String::from).collect();
             }
             n if n.eq_ignore_ascii_case("id") => {
-                id = Some(val.to_string());
+                id = Some(value.to_string());
             }
             n if n.eq_ignore_ascii_case("style") => {
                 // ignore this lol
@@ -107,7 +183,15 @@ fn attrs(input: &str) -> IResult<&str, ElementData> {
         }
     }
     tracing::debug!(?classes, ?id, "parsed attrs");
-    Ok((remaining, ElementData { id, classes }))
+    Ok((
+        remaining,
+        ElementData {
+            tag_name: None,
+            id,
+            classes,
+            attributes,
+        },
+    ))
 }
 
 #[tracing::instrument(level = "trace", err)]
@@ -116,6 +200,7 @@ fn open_tag(input: &str) -> IResult<&str, ElementData> {
     let (remaining, (tag_name, mut attrs)) =
         delimited(tag("<"), pair(identifier, attrs), tag(">"))(input)?;
     attrs.classes.insert(tag_name.to_string());
+    attrs.tag_name = Some(tag_name.to_string());
     Ok((remaining, attrs))
 }
 
@@ -127,17 +212,55 @@ fn self_closing_tag<'a>(name: &'static str) -> impl FnMut(&'a str) -> IResult<&s
             preceded(opt(tag("/")), tag(">")),
         )(input)?;
         attrs.classes.insert(name.to_string());
+        attrs.tag_name = Some(name.to_string());
         Ok((
             remaining,
             Node {
                 children: Vec::new(),
                 node_data: NodeData::Element(attrs),
+                span: Span::consumed(input, remaining),
             },
         ))
     })
 }
 
-fn close_tag<'a>(name: &'a str) -> impl FnMut(&'a str) -> IResult<&str, &str> {
+/// The contents of a raw-text element like `<script>`, taken verbatim up to
+/// its close tag. Character references are decoded if `escapable` is set.
+fn raw_text<'a>(
+    tag_name: &'a str,
+    escapable: bool,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Node> {
+    move |input: &'a str| {
+        let end = input
+            .match_indices("</")
+            .map(|(i, _)| i)
+            .find(|&i| ends_raw_text(&input[i + 2..], tag_name))
+            .unwrap_or(input.len());
+        if end == 0 {
+            return Err(nom::Err::Error(VerboseError::from_error_kind(
+                input,
+                nom::error::ErrorKind::TakeUntil,
+            )));
+        }
+        let (text, remaining) = input.split_at(end);
+        let mut node = Node::from(if escapable {
+            decode_entities(text)
+        } else {
+            text.to_string()
+        });
+        node.span = Span::consumed(input, remaining);
+        Ok((remaining, node))
+    }
+}
+
+/// Whether `rest`, which follows a `</`, closes the element `tag_name`.
+fn ends_raw_text(rest: &str, tag_name: &str) -> bool {
+    rest.get(..tag_name.len())
+        .is_some_and(|name| name.eq_ignore_ascii_case(tag_name))
+        && rest[tag_name.len()..].starts_with('>')
+}
+
+fn close_tag<'a>(name: &'a str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
     move |input| {
         let span = tracing::trace_span!("close_tag", ?name, ?input,);
         let _e = span.enter();
@@ -149,24 +272,6 @@ fn ws1(input: &str) -> IResult<&str, ()> {
     value((), many1(alt((comment, multispace1))))(input)
 }
 
-fn non_space_ws1(input: &str) -> IResult<&str, ()> {
-    value(
-        (),
-        many1(alt((
-            comment,
-            preceded(tag("\r\n"), multispace0),
-            preceded(tag("\n"), multispace0),
-            tag("\t"),
-        ))),
-    )(input)
-}
-
-fn skip_non_space_ws<'a, T>(
-    parser: impl FnMut(&str) -> IResult<&str, T>,
-) -> impl FnMut(&'a str) -> IResult<&str, T> {
-    delimited(opt(non_space_ws1), parser, opt(non_space_ws1))
-}
-
 fn skip_ws<'a, T>(
     parser: impl FnMut(&'a str) -> IResult<&str, T>,
 ) -> impl FnMut(&'a str) -> IResult<&str, T> {
@@ -189,20 +294,37 @@ fn tag_children(input: &str) -> IResult<&str, Node> {
         ),
     )(input)?;
     attrs.classes.insert(tag_name.to_string());
-    let (remaining, children) = terminated(
-        // Only skip "non-space" whitespace here. Spaces may be part of a
-        // text node.
-        context(
-            "children",
-            preceded(opt(non_space_ws1), many0(skip_non_space_ws(element))),
-        ),
-        context("close tag", close_tag(tag_name)),
-    )(remaining)?;
+    attrs.tag_name = Some(tag_name.to_string());
+    // As in HTML, a single newline directly after `<pre>` or `<textarea>` is
+    // ignored.
+    let remaining =
+        if tag_name.eq_ignore_ascii_case("pre") || tag_name.eq_ignore_ascii_case("textarea") {
+            let (remaining, _) = opt(alt((tag("\r\n"), tag("\n"))))(remaining)?;
+            remaining
+        } else {
+            remaining
+        };
+    let is = |names: &[&str]| names.iter().any(|n| tag_name.eq_ignore_ascii_case(n));
+    let (remaining, children) = if is(RAW_TEXT_ELEMENTS) || is(ESCAPABLE_RAW_TEXT_ELEMENTS) {
+        let (remaining, text) = terminated(
+            opt(raw_text(tag_name, is(ESCAPABLE_RAW_TEXT_ELEMENTS))),
+            context("close tag", close_tag(tag_name)),
+        )(remaining)?;
+        (remaining, text.into_iter().collect())
+    } else {
+        // Whitespace between children is kept as text, so only comments are
+        // skipped here.
+        terminated(
+            context("children", many0(preceded(many0(comment), element))),
+            preceded(many0(comment), context("close tag", close_tag(tag_name))),
+        )(remaining)?
+    };
     Ok((
         remaining,
         Node {
             children,
             node_data: NodeData::Element(attrs),
+            span: Span::consumed(input, remaining),
         },
     ))
 }
@@ -211,6 +333,14 @@ fn tag_children(input: &str) -> IResult<&str, Node> {
 mod tests {
     use super::*;
 
+    fn attr(name: &str, value: &str) -> Attribute {
+        Attribute {
+            name: name.to_string(),
+            value: value.to_string(),
+            span: Span::default(),
+        }
+    }
+
     fn trace_init() {
         let _ = tracing_subscriber::fmt()
             .with_test_writer()
@@ -230,7 +360,15 @@ mod tests {
 
         let mut classes = HashSet::new();
         classes.insert(String::from("a"));
-        assert_eq!(parsed, ElementData { classes, id: None });
+        assert_eq!(
+            parsed,
+            ElementData {
+                tag_name: Some("a".to_string()),
+                classes,
+                id: None,
+                attributes: vec![],
+            }
+        );
         assert_eq!(remaining, "");
     }
 
@@ -245,7 +383,15 @@ mod tests {
 
         let mut classes = HashSet::new();
         classes.insert(String::from("a"));
-        assert_eq!(parsed, ElementData { classes, id: None });
+        assert_eq!(
+            parsed,
+            ElementData {
+                tag_name: Some("a".to_string()),
+                classes,
+                id: None,
+                attributes: vec![attr("href", "my cool website")],
+            }
+        );
         assert_eq!(remaining, "");
     }
 
@@ -262,7 +408,15 @@ mod tests {
             .into_iter()
             .map(String::from)
             .collect();
-        assert_eq!(parsed, ElementData { classes, id: None });
+        assert_eq!(
+            parsed,
+            ElementData {
+                tag_name: Some("a".to_string()),
+                classes,
+                id: None,
+                attributes: vec![attr("class", "foo bar baz")],
+            }
+        );
         assert_eq!(remaining, "");
     }
 
@@ -279,7 +433,19 @@ mod tests {
             .into_iter()
             .map(String::from)
             .collect();
-        assert_eq!(parsed, ElementData { classes, id: None });
+        assert_eq!(
+            parsed,
+            ElementData {
+                tag_name: Some("a".to_string()),
+                classes,
+                id: None,
+                attributes: vec![
+                    attr("href", "my website"),
+                    attr("class", "foo bar baz"),
+                    attr("something", "lol"),
+                ],
+            }
+        );
         assert_eq!(remaining, "");
     }
 
@@ -299,8 +465,14 @@ mod tests {
         assert_eq!(
             parsed,
             ElementData {
+                tag_name: Some("a".to_string()),
                 classes,
-                id: Some("cool".to_string())
+                id: Some("cool".to_string()),
+                attributes: vec![
+                    attr("href", "my website"),
+                    attr("class", "foo bar baz"),
+                    attr("id", "cool"),
+                ],
             }
         );
         assert_eq!(remaining, "");
@@ -318,16 +490,22 @@ mod tests {
         let b = Node {
             children: Vec::new(),
             node_data: NodeData::Element(ElementData {
+                tag_name: Some("b".to_string()),
                 classes: Some("b".to_string()).into_iter().collect(),
                 id: None,
+                attributes: vec![],
             }),
+            span: Span::default(),
         };
         let a = Node {
             children: vec![b],
             node_data: NodeData::Element(ElementData {
+                tag_name: Some("a".to_string()),
                 classes: Some("a".to_string()).into_iter().collect(),
                 id: None,
+                attributes: vec![],
             }),
+            span: Span::default(),
         };
 
         assert_eq!(parsed, a);
@@ -368,11 +546,14 @@ mod tests {
             .map_err(|e| e.to_string())
             .expect("it should parse");
         let a = Node {
-            children: vec![],
+            children: vec![Node::from("\n")],
             node_data: NodeData::Element(ElementData {
+                tag_name: Some("a".to_string()),
                 classes: Some("a".to_string()).into_iter().collect(),
                 id: None,
+                attributes: vec![],
             }),
+            span: Span::default(),
         };
 
         assert_eq!(parsed, a);
@@ -388,11 +569,14 @@ mod tests {
             .map_err(|e| e.to_string())
             .expect("it should parse");
         let a = Node {
-            children: vec![Node::from("Hello world")],
+            children: vec![Node::from("\nHello world\n")],
             node_data: NodeData::Element(ElementData {
+                tag_name: Some("a".to_string()),
                 classes: Some("a".to_string()).into_iter().collect(),
                 id: None,
+                attributes: vec![],
             }),
+            span: Span::default(),
         };
 
         assert_eq!(parsed, a);
@@ -409,9 +593,12 @@ mod tests {
         let a = Node {
             children: vec![Node::from("Hello world!")],
             node_data: NodeData::Element(ElementData {
+                tag_name: Some("a".to_string()),
                 classes: Some("a".to_string()).into_iter().collect(),
                 id: None,
+                attributes: vec![],
             }),
+            span: Span::default(),
         };
 
         assert_eq!(parsed, a);
@@ -429,16 +616,22 @@ mod tests {
         let b = Node {
             children: vec![Node::from("world")],
             node_data: NodeData::Element(ElementData {
+                tag_name: Some("b".to_string()),
                 classes: Some("b".to_string()).into_iter().collect(),
                 id: None,
+                attributes: vec![],
             }),
+            span: Span::default(),
         };
         let a = Node {
             children: vec![Node::from("Hello "), b, Node::from("!")],
             node_data: NodeData::Element(ElementData {
+                tag_name: Some("a".to_string()),
                 classes: Some("a".to_string()).into_iter().collect(),
                 id: None,
+                attributes: vec![],
             }),
+            span: Span::default(),
         };
 
         assert_eq!(parsed, a);
@@ -457,25 +650,151 @@ mod tests {
         let b = Node {
             children: vec![Node::from("world")],
             node_data: NodeData::Element(ElementData {
+                tag_name: Some("b".to_string()),
                 classes: Some("b".to_string()).into_iter().collect(),
                 id: Some("thing".to_string()),
+                attributes: vec![attr("id", "thing")],
             }),
+            span: Span::default(),
         };
         let a = Node {
             children: vec![Node::from("Hello "), b, Node::from("!")],
             node_data: NodeData::Element(ElementData {
+                tag_name: Some("a".to_string()),
                 classes: vec!["a", "foo", "bar"]
                     .into_iter()
                     .map(String::from)
                     .collect(),
                 id: None,
+                attributes: vec![attr("class", "foo bar"), attr("href", "my cool website")],
             }),
+            span: Span::default(),
         };
 
         assert_eq!(parsed, a);
         assert_eq!(remaining, "");
     }
 
+    #[test]
+    fn pre_keeps_whitespace() {
+        trace_init();
+
+        let html = "<pre>\n  fn main() {\n      <!-- hi -->work();\n  }\n</pre>";
+        let (remaining, parsed) = dbg!(element(html))
+            .map_err(|e| e.to_string())
+            .expect("it should parse");
+        let pre = Node {
+            children: vec![
+                Node::from("  fn main() {\n      "),
+                Node::from("work();\n  }\n"),
+            ],
+            node_data: NodeData::Element(ElementData {
+                tag_name: Some("pre".to_string()),
+                classes: Some("pre".to_string()).into_iter().collect(),
+                id: None,
+                attributes: vec![],
+            }),
+            span: Span::default(),
+        };
+
+        assert_eq!(parsed, pre);
+        assert_eq!(remaining, "");
+    }
+
+    #[test]
+    fn fragment_with_trailing_text() {
+        trace_init();
+
+        let html = "<b>ok</b> 3 files";
+        let (remaining, parsed) = dbg!(fragment(html))
+            .map_err(|e| e.to_string())
+            .expect("it should parse");
+        let b = Node {
+            children: vec![Node::from("ok")],
+            node_data: NodeData::Element(ElementData {
+                tag_name: Some("b".to_string()),
+                classes: Some("b".to_string()).into_iter().collect(),
+                id: None,
+                attributes: vec![],
+            }),
+            span: Span::default(),
+        };
+
+        assert_eq!(parsed, vec![b, Node::from(" 3 files")]);
+        assert_eq!(remaining, "");
+    }
+
+    #[test]
+    fn trailing_input_doesnt_parse() {
+        trace_init();
+
+        let html = "<a></a> trailing";
+        let res = dbg!(document(html));
+        assert!(res.is_err(), "html: {:?}", html);
+
+        let html = "<a></a></b>";
+        let res = dbg!(fragment(html));
+        assert!(res.is_err(), "html: {:?}", html);
+
+        let html = "<a></a>\n<!-- done -->\n";
+        let res = dbg!(document(html));
+        assert!(res.is_ok(), "html: {:?}", html);
+    }
+
+    #[test]
+    fn spans() {
+        trace_init();
+
+        let html = "<a id=\"x\">\n  Hello <b>world</b></a>";
+        let node = html.parse::<Node>().expect("it should parse");
+        assert_eq!(node.span().range(), 0..html.len());
+        assert_eq!(node.attributes()[0].span().range(), 3..9);
+
+        let b = &node.children[1];
+        assert_eq!(&html[b.span().range()], "<b>world</b>");
+        assert_eq!((b.span().start.line, b.span().start.column), (2, 9));
+        assert_eq!((b.span().end.line, b.span().end.column), (2, 21));
+    }
+
+    #[test]
+    fn entities() {
+        trace_init();
+
+        let html = "<a title=\"&quot;x&quot; &amp y\">1 &lt; 2 &#x1F970;&#33; &bogus;</a>";
+        let node = html.parse::<Node>().expect("it should parse");
+        assert_eq!(node.attribute("title"), Some("\"x\" &amp y"));
+        assert_eq!(node.children[0].text(), Some("1 < 2 🥰! &bogus;"));
+    }
+
+    #[test]
+    fn raw_text_elements() {
+        trace_init();
+
+        let html = "<div><script>if (a < b && c) { x = \"</b>\"; }</script>\
+                    <style>.a { } <!-- --></STYLE>\
+                    <textarea>\n&lt;b&gt; <i></textarea><title></title></div>";
+        let node = html.parse::<Node>().expect("it should parse");
+        let texts = node
+            .children
+            .iter()
+            .map(|c| c.children.iter().filter_map(Node::text).collect::<String>())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            vec![
+                "if (a < b && c) { x = \"</b>\"; }",
+                ".a { } <!-- -->",
+                "<b> <i>",
+                "",
+            ]
+        );
+        assert_eq!(
+            &html[node.children[0].children[0].span().range()],
+            "if (a < b && c) { x = \"</b>\"; }"
+        );
+        assert!("<script>a</scriptx>".parse::<Node>().is_err());
+    }
+
     #[test]
     fn bad_p() {
         trace_init();
@@ -0,0 +1,326 @@
+//! Writing content trees back out as markup.
+//!
+//! `format!("{}", node)` produces markup that parses back to an equal tree,
+//! and `format!("{:#}", node)` pretty-prints it with one element per line.
+//! Markup can't express elements without a tag name or empty text, so
+//! elements built with [`Node::new`] are written as `div`s, and empty text
+//! nodes are left out.
+//! Pretty-printing adds and removes whitespace between elements, so it only
+//! round-trips for documents where that whitespace is insignificant.
+
+use super::*;
+
+/// Elements that are written without a close tag when they have no children.
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr", "command", "keygen", "menuitem",
+];
+
+/// The tag written for elements built without one, using [`Node::new`].
+const DEFAULT_TAG: &str = "div";
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            write_pretty(self, f, 0)
+        } else {
+            write_node(self, f)
+        }
+    }
+}
+
+fn write_node(node: &Node, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write_child(node, None, f)
+}
+
+/// Write a node inside `parent`.
+fn write_child(
+    node: &Node,
+    parent: Option<&ElementData>,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    match node.node_data {
+        NodeData::Text(ref t) => f.write_str(&escape_text(t)),
+        NodeData::Element(ref e) if node.children.is_empty() && is_void(e) => {
+            // Inside an element with the same tag, the parent's close tag
+            // would be read as this element's.
+            let nested = parent.is_some_and(|p| p.tag_name() == e.tag_name());
+            write_open_tag(e, f)?;
+            f.write_str(if nested { "/>" } else { ">" })
+        }
+        NodeData::Element(ref e) => {
+            write_open_tag(e, f)?;
+            f.write_str(">")?;
+            let drops_newline = is_one_of(e, &["pre", "textarea"]);
+            if drops_newline && starts_with_newline(node) {
+                // The parser drops one newline after `<pre>` and `<textarea>`.
+                f.write_str("\n")?;
+            }
+            if is_one_of(e, RAW_TEXT_ELEMENTS) {
+                // Raw text can't be escaped, and is read back verbatim.
+                for t in node.children.iter().filter_map(Node::text) {
+                    f.write_str(t)?;
+                }
+            } else {
+                write_children(&node.children, e, f)?;
+            }
+            write!(f, "</{}>", tag_name(e))
+        }
+    }
+}
+
+/// Write a list of sibling nodes. Adjacent text nodes are separated by an
+/// empty comment, since the parser would otherwise merge them.
+fn write_children(
+    children: &[Node],
+    parent: &ElementData,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    let mut previous_was_text = false;
+    for child in children {
+        let is_text = child.text().is_some();
+        if is_text && previous_was_text {
+            f.write_str("<!---->")?;
+        }
+        write_child(child, Some(parent), f)?;
+        previous_was_text = is_text;
+    }
+    Ok(())
+}
+
+fn write_pretty(node: &Node, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+    let indent = "  ".repeat(depth);
+    match node.node_data {
+        NodeData::Text(ref t) => {
+            let t = t.trim();
+            if !t.is_empty() {
+                writeln!(f, "{}{}", indent, escape_text(t))?;
+            }
+            Ok(())
+        }
+        NodeData::Element(ref e) => {
+            f.write_str(&indent)?;
+            if e.tag_name() == Some("pre") || node.children.iter().all(|c| c.text().is_some()) {
+                // Text-only elements stay on one line, and preformatted
+                // text must be written exactly.
+                write_node(node, f)?;
+                return writeln!(f);
+            }
+            write_open_tag(e, f)?;
+            writeln!(f, ">")?;
+            for child in &node.children {
+                write_pretty(child, f, depth + 1)?;
+            }
+            writeln!(f, "{}</{}>", indent, tag_name(e))
+        }
+    }
+}
+
+/// Write an open tag, up to but not including its closing `>`.
+fn write_open_tag(e: &ElementData, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "<{}", tag_name(e))?;
+    let has = |name| e.attribute(name).is_some();
+    // Elements built by hand may have an id and classes but no attributes.
+    if !has("id") {
+        if let Some(ref id) = e.id {
+            write!(f, " id=\"{}\"", escape_attribute(id))?;
+        }
+    }
+    if !has("class") {
+        let mut classes = e
+            .classes
+            .iter()
+            .filter(|c| Some(c.as_str()) != e.tag_name())
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        if !classes.is_empty() {
+            classes.sort_unstable();
+            write!(f, " class=\"{}\"", escape_attribute(&classes.join(" ")))?;
+        }
+    }
+    for attribute in &e.attributes {
+        write!(
+            f,
+            " {}=\"{}\"",
+            attribute.name,
+            escape_attribute(&attribute.value)
+        )?;
+    }
+    Ok(())
+}
+
+fn tag_name(e: &ElementData) -> &str {
+    e.tag_name().unwrap_or(DEFAULT_TAG)
+}
+
+fn is_void(e: &ElementData) -> bool {
+    is_one_of(e, VOID_ELEMENTS)
+}
+
+fn is_one_of(e: &ElementData, names: &[&str]) -> bool {
+    names
+        .iter()
+        .any(|n| e.tag_name().is_some_and(|t| t.eq_ignore_ascii_case(n)))
+}
+
+fn starts_with_newline(node: &Node) -> bool {
+    node.children
+        .first()
+        .and_then(Node::text)
+        .is_some_and(|t| t.starts_with('\n'))
+}
+
+fn escape_text(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn escape_attribute(s: &str) -> String {
+    s.replace('&', "&amp;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn round_trip(html: &str) {
+        let node = html.parse::<Node>().expect("it should parse");
+        let markup = node.to_string();
+        let reparsed = markup
+            .parse::<Node>()
+            .expect("serialized markup should parse");
+        assert_eq!(reparsed, node, "markup: {:?}", markup);
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip("<a>Hello <b id=\"thing\">world</b>!</a>");
+        round_trip("<a class=\"foo bar\" href=\"my &quot;cool&quot; website\">x</a>");
+        round_trip("<p>1 &lt; 2 &amp;&amp; 3 &gt; 2<br>next<hr class=\"rule\"/></p>");
+        round_trip("<pre>\n\nindented\n    code</pre>");
+        round_trip("<ul>\n  <li data-x=\"1\">one</li>\n  <li>two</li>\n</ul>");
+        round_trip("<a>one<!-- split -->two</a>");
+        round_trip("<div><script>a < b && c</script><textarea>\n\nx &lt;</textarea></div>");
+    }
+
+    #[test]
+    fn built_trees_round_trip() {
+        let mut node = Node::element(
+            "div",
+            vec![
+                Node::from("a < b"),
+                Node::from("\"quoted\""),
+                Node::element("br", vec![Node::from("child")]),
+            ],
+        );
+        node.set_id(Some("x&y".to_string()));
+        node.add_class("c");
+        let markup = node.to_string();
+        assert_eq!(
+            markup,
+            "<div id=\"x&amp;y\" class=\"c\">a &lt; b<!---->\"quoted\"<br>child</br></div>"
+        );
+        assert_eq!(markup.parse::<Node>(), Ok(node));
+    }
+
+    /// A xorshift generator, so that the same trees are tested every run.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    fn random_element(rng: &mut Rng, depth: usize) -> Node {
+        const TAGS: &[&str] = &["p", "b", "pre", "br", "li"];
+        let children = (0..rng.below(4))
+            .map(|_| random_node(rng, depth))
+            .collect::<Vec<_>>();
+        let mut node = match rng.below(3) {
+            0 => Node::new(children, None, HashSet::new()),
+            _ => Node::element(TAGS[rng.below(TAGS.len())], children),
+        };
+        if rng.below(2) == 0 {
+            node.set_id(Some("x&\"y\"".to_string()));
+        }
+        if rng.below(2) == 0 {
+            node.add_class("c");
+        }
+        if rng.below(3) == 0 {
+            node.set_attribute("data-v", Some("1 < 2".to_string()));
+        }
+        node
+    }
+
+    fn random_node(rng: &mut Rng, depth: usize) -> Node {
+        const TEXT: &[&str] = &["", " ", "a", "\nb", "x < y", "&amp;", "\"q\" 'q'"];
+        if depth == 0 || rng.below(3) == 0 {
+            Node::from(TEXT[rng.below(TEXT.len())])
+        } else {
+            random_element(rng, depth - 1)
+        }
+    }
+
+    /// The tree that a node's markup parses back to: elements without a tag
+    /// name become `div`s, and empty text is dropped.
+    fn normalized(node: &Node) -> Node {
+        let mut node = node.clone();
+        if let NodeData::Element(ref mut e) = node.node_data {
+            if e.tag_name.is_none() {
+                e.tag_name = Some(DEFAULT_TAG.to_string());
+                e.classes.insert(DEFAULT_TAG.to_string());
+            }
+        }
+        node.children = node
+            .children
+            .iter()
+            .filter(|child| child.text() != Some(""))
+            .map(normalized)
+            .collect();
+        node
+    }
+
+    #[test]
+    fn random_trees_round_trip() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..100 {
+            let node = random_element(&mut rng, 3);
+            let markup = node.to_string();
+            assert_eq!(
+                markup.parse::<Node>(),
+                Ok(normalized(&node)),
+                "markup: {:?}",
+                markup
+            );
+        }
+
+        // Elements without a tag name, and empty text, which the parser
+        // never produces.
+        let node = Node::new(
+            vec![Node::element("p", vec![Node::from("")])],
+            Some("x".to_string()),
+            Some("a".to_string()).into_iter().collect(),
+        );
+        assert_eq!(node.to_string(), "<div id=\"x\" class=\"a\"><p></p></div>");
+        assert_ne!(node.to_string().parse::<Node>(), Ok(node.clone()));
+        assert_eq!(node.to_string().parse::<Node>(), Ok(normalized(&node)));
+    }
+
+    #[test]
+    fn pretty() {
+        let node =
+            "<div id=\"root\"><p>Hello <b>world</b></p>\n<pre>  a\n b</pre><p>text</p></div>"
+                .parse::<Node>()
+                .unwrap();
+        assert_eq!(
+            format!("{:#}", node),
+            "<div id=\"root\">\n  <p>\n    Hello\n    <b>world</b>\n  </p>\n  <pre>  a\n b</pre>\n  <p>text</p>\n</div>\n"
+        );
+    }
+}
@@ -1,29 +1,35 @@
 use super::layout::*;
-use super::style::Border;
+use super::style::{Border, Color, ColorMode};
+use std::fmt;
+use std::io::{self, Write};
 
 pub enum DisplayCommand<'a> {
     FilledBox(Rect, char),
-    BorderBox(Rect, Borders),
-    Text(Rect, &'a str),
+    BorderBox(Rect, Borders, BorderColors),
+    Text(Rect, &'a str, Option<Color>),
 }
 
 type DisplayList<'a> = Vec<DisplayCommand<'a>>;
 
-pub fn build_display_list<'a>(layout_root: &LayoutBox<'a>) -> DisplayList<'a> {
+pub fn build_display_list(layout_tree: &LayoutTree) -> DisplayList<'_> {
     let mut list = Vec::new();
-    render_layout_box(&mut list, layout_root, &mut 0);
-    return list;
+    if let Some(root) = layout_tree.root() {
+        render_layout_box(&mut list, layout_tree, root, &mut 0);
+    }
+    list
 }
 
 fn render_layout_box<'a>(
     list: &mut DisplayList<'a>,
-    layout_box: &LayoutBox<'a>,
+    layout_tree: &'a LayoutTree,
+    id: BoxId,
     char_idx: &mut usize,
 ) {
+    let layout_box = &layout_tree[id];
     let d = &layout_box.dimensions;
 
     match layout_box.box_type {
-        BoxType::BlockNode(_) => {
+        BoxType::BlockNode(_) | BoxType::BlockPseudo(..) => {
             // choose a bg character
             let chars: Vec<char> = "░▒▓🮔🮽🮿".chars().collect();
             let bg = chars[*char_idx % chars.len()];
@@ -32,46 +38,355 @@ fn render_layout_box<'a>(
             let bg = chars[*char_idx % chars.len()];
             *char_idx += 1;
             list.push(DisplayCommand::FilledBox(d.content_box(), bg));
-            list.push(DisplayCommand::BorderBox(d.border_box(), d.border));
+            list.push(DisplayCommand::BorderBox(
+                d.border_box(),
+                d.border,
+                layout_box.border_colors,
+            ));
         }
-        BoxType::InlineNode(node) => {
-            if let Some(text) = node.node().text() {
-                list.push(DisplayCommand::Text(d.content_box(), text));
+        BoxType::InlineNode(_)
+        | BoxType::InlinePseudo(..)
+        | BoxType::GeneratedText
+        | BoxType::Marker(_) => {
+            for fragment in &layout_box.fragments {
+                list.push(DisplayCommand::Text(
+                    fragment.rect,
+                    &fragment.text,
+                    layout_box.color,
+                ));
             }
         }
         BoxType::Anonymous => {}
     }
 
-    for child in &layout_box.children {
-        render_layout_box(list, child, char_idx);
+    for &child in &layout_box.children {
+        render_layout_box(list, layout_tree, child, char_idx);
     }
 }
 
 pub struct DebugCanvas {
-    width: usize,
-    height: usize,
-    data: Vec<Vec<char>>,
+    grid: Grid,
 }
 
 impl DebugCanvas {
     pub fn new(width: usize, height: usize) -> DebugCanvas {
         DebugCanvas {
-            width,
-            height,
-            data: (0..height)
-                .map(|_| (0..width).map(|_| '🮕').collect())
-                .collect(),
+            grid: Grid::new(width, height, '🮕'),
         }
     }
 
     pub fn print(&self) {
-        println!("Debug Canvas {}x{}", self.width, self.height);
-        for row in &self.data {
-            println!("{}", row.iter().collect::<String>());
+        print!("{}", self);
+    }
+
+    pub fn paint(&mut self, display_list: &DisplayList) {
+        self.grid.paint(display_list);
+    }
+}
+
+impl fmt::Display for DebugCanvas {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Debug Canvas {}x{}", self.grid.width, self.grid.height)?;
+        for row in &self.grid.data {
+            writeln!(f, "{}", row.iter().map(|&(c, _)| c).collect::<String>())?;
+        }
+        Ok(())
+    }
+}
+
+/// A canvas that draws a document as plain text, with unpainted cells left
+/// blank.
+pub struct TextCanvas {
+    grid: Grid,
+}
+
+impl TextCanvas {
+    pub fn new(width: usize, height: usize) -> TextCanvas {
+        TextCanvas {
+            grid: Grid::text(width, height),
         }
     }
 
+    /// A canvas just big enough for everything in a laid-out tree.
+    pub fn for_layout(layout_tree: &LayoutTree) -> TextCanvas {
+        let (width, height) = layout_size(layout_tree);
+        TextCanvas::new(width, height)
+    }
+
+    /// Write colored text, with escape sequences, in `mode`.
+    pub fn with_color_mode(mut self, mode: ColorMode) -> TextCanvas {
+        self.grid.color_mode = mode;
+        self
+    }
+
     pub fn paint(&mut self, display_list: &DisplayList) {
+        self.grid.paint(display_list);
+    }
+}
+
+/// The width and height needed to show everything in a laid-out tree.
+pub(crate) fn layout_size(layout_tree: &LayoutTree) -> (usize, usize) {
+    let (mut width, mut height) = (0, 0);
+    let mut stack = layout_tree.root().into_iter().collect::<Vec<_>>();
+    while let Some(id) = stack.pop() {
+        let layout_box = &layout_tree[id];
+        let rects = std::iter::once(layout_box.dimensions.margin_box())
+            .chain(layout_box.fragments.iter().map(|f| f.rect));
+        for rect in rects {
+            width = width.max(rect.x + rect.width);
+            height = height.max(rect.y + rect.height);
+        }
+        stack.extend(&layout_box.children);
+    }
+    (width.max(0) as usize, height.max(0) as usize)
+}
+
+/// Writes the canvas one row per line, without trailing whitespace or blank
+/// lines at the end.
+impl fmt::Display for TextCanvas {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows = self
+            .grid
+            .data
+            .iter()
+            .map(|row| {
+                let end = row
+                    .iter()
+                    .rposition(|&(c, _)| !c.is_whitespace())
+                    .map_or(0, |i| i + 1);
+                &row[..end]
+            })
+            .collect::<Vec<_>>();
+        let end = rows
+            .iter()
+            .rposition(|row| !row.is_empty())
+            .map_or(0, |i| i + 1);
+        for row in &rows[..end] {
+            write_cells(row, self.grid.color_mode, f)?;
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write a run of cells, setting the color wherever it changes, and
+/// resetting it after the last cell.
+fn write_cells(cells: &[Cell], mode: ColorMode, out: &mut impl fmt::Write) -> fmt::Result {
+    let mut pen = None;
+    for &(c, color) in cells {
+        if color != pen {
+            match color.and_then(|color| color.sgr(mode)) {
+                Some(sgr) => write!(out, "\x1b[{}m", sgr)?,
+                None => write!(out, "\x1b[0m")?,
+            }
+            pen = color;
+        }
+        out.write_char(c)?;
+    }
+    if pen.is_some() {
+        write!(out, "\x1b[0m")?;
+    }
+    Ok(())
+}
+
+/// A canvas for drawing successive frames to a terminal.
+///
+/// Each frame is painted into a back buffer, and [`TerminalCanvas::present`]
+/// writes only the cells that differ from the frame before it, scrolling
+/// the terminal when rows have moved up or down. The canvas is drawn at the
+/// top left of the screen, one character per cell.
+pub struct TerminalCanvas {
+    back: Grid,
+    /// What the terminal is showing, if known.
+    front: Option<Grid>,
+}
+
+impl TerminalCanvas {
+    pub fn new(width: usize, height: usize) -> TerminalCanvas {
+        TerminalCanvas {
+            back: Grid::text(width, height),
+            front: None,
+        }
+    }
+
+    /// Write colored text, with escape sequences, in `mode`.
+    pub fn with_color_mode(mut self, mode: ColorMode) -> TerminalCanvas {
+        self.back.color_mode = mode;
+        self
+    }
+
+    /// Paint onto the next frame.
+    pub fn paint(&mut self, display_list: &DisplayList) {
+        self.back.paint(display_list);
+    }
+
+    /// Forget what the terminal is showing, so the next frame is drawn in
+    /// full.
+    pub fn invalidate(&mut self) {
+        self.front = None;
+    }
+
+    /// Write the next frame to `out`, and start a new, blank one.
+    pub fn present(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let mut screen = match self.front.take() {
+            Some(front) => front,
+            None => {
+                // Clear the canvas, so every cell counts as changed.
+                write!(out, "\x1b[H\x1b[J")?;
+                Grid::new(self.back.width, self.back.height, '\0')
+            }
+        };
+        scroll(&mut screen, &self.back, out)?;
+        for (y, (old, new)) in screen.data.iter().zip(&self.back.data).enumerate() {
+            write_row_changes(y, old, new, self.back.color_mode, out)?;
+        }
+        write!(out, "\x1b[{};1H", self.back.height + 1)?;
+        out.flush()?;
+
+        let blank = Grid {
+            color_mode: self.back.color_mode,
+            ..Grid::text(self.back.width, self.back.height)
+        };
+        self.front = Some(std::mem::replace(&mut self.back, blank));
+        Ok(())
+    }
+}
+
+/// If rows of `old` reappear higher or lower in `new`, scroll them into
+/// place, so they don't need to be written again.
+fn scroll(old: &mut Grid, new: &Grid, out: &mut impl Write) -> io::Result<()> {
+    // Only the rows between the first and last changed rows are scrolled.
+    let changed = |y: &usize| old.data[*y] != new.data[*y];
+    let top = match (0..new.height).find(changed) {
+        Some(top) => top,
+        None => return Ok(()),
+    };
+    let bottom = (0..new.height).rev().find(changed).map_or(top, |b| b + 1);
+
+    // The shift that lets the most rows be reused, as long as that saves
+    // more than a row or so of output.
+    let reused = |shift: isize| {
+        (top..bottom)
+            .filter(|&y| {
+                let from = y as isize + shift;
+                (top as isize..bottom as isize).contains(&from)
+                    && old.data[from as usize] == new.data[y]
+                    && new.data[y].iter().any(|&(c, _)| c != ' ')
+            })
+            .count()
+    };
+    let height = (bottom - top) as isize;
+    let best = (1..height)
+        .flat_map(|k| vec![k, -k])
+        .map(|shift| (reused(shift), shift))
+        .max_by_key(|&(reused, shift)| (reused, -shift.abs()));
+    let shift = match best {
+        Some((reused, shift)) if reused >= 2 => shift,
+        _ => return Ok(()),
+    };
+
+    // Limit scrolling to the changed rows, scroll, then reset the region.
+    write!(out, "\x1b[{};{}r", top + 1, bottom)?;
+    if shift > 0 {
+        write!(out, "\x1b[{}S", shift)?;
+    } else {
+        write!(out, "\x1b[{}T", -shift)?;
+    }
+    write!(out, "\x1b[r")?;
+
+    let blank = vec![(' ', None); old.width];
+    let rows = old.data[top..bottom].to_vec();
+    for y in top..bottom {
+        let from = y as isize + shift;
+        old.data[y] = if (top as isize..bottom as isize).contains(&from) {
+            rows[from as usize - top].clone()
+        } else {
+            blank.clone()
+        };
+    }
+    Ok(())
+}
+
+/// Write the cells that differ between two versions of row `y`.
+fn write_row_changes(
+    y: usize,
+    old: &[Cell],
+    new: &[Cell],
+    mode: ColorMode,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    // Short runs of unchanged cells are rewritten, since that is cheaper
+    // than moving the cursor past them.
+    const MAX_GAP: usize = 4;
+    let mut x = 0;
+    while x < new.len() {
+        if old[x] == new[x] {
+            x += 1;
+            continue;
+        }
+        let start = x;
+        let mut end = x + 1;
+        while end < new.len() {
+            match (end..new.len().min(end + MAX_GAP)).find(|&i| old[i] != new[i]) {
+                Some(next) => end = next + 1,
+                None => break,
+            }
+        }
+        let mut run = String::new();
+        write_cells(&new[start..end], mode, &mut run).expect("writing to a String doesn't fail");
+        write!(out, "\x1b[{};{}H{}", y + 1, start + 1, run)?;
+        x = end;
+    }
+    Ok(())
+}
+
+/// A character and the color it is drawn in.
+type Cell = (char, Option<Color>);
+
+/// A grid of character cells that display lists are painted into.
+#[derive(Clone)]
+struct Grid {
+    width: usize,
+    height: usize,
+    data: Vec<Vec<Cell>>,
+    /// Whether to draw `FilledBox` commands, which shade block boxes so
+    /// their extent is visible when debugging.
+    fills: bool,
+    /// How colors are written. Colors aren't kept in [`ColorMode::None`].
+    color_mode: ColorMode,
+}
+
+impl Grid {
+    fn new(width: usize, height: usize, fill: char) -> Grid {
+        Grid {
+            width,
+            height,
+            data: vec![vec![(fill, None); width]; height],
+            fills: true,
+            color_mode: ColorMode::None,
+        }
+    }
+
+    /// The color to keep for something drawn in `color`.
+    fn color(&self, color: Option<Color>) -> Option<Color> {
+        match self.color_mode {
+            ColorMode::None => None,
+            _ => color,
+        }
+    }
+
+    /// A blank grid for text output, where block boxes aren't shaded.
+    fn text(width: usize, height: usize) -> Grid {
+        Grid {
+            fills: false,
+            ..Grid::new(width, height, ' ')
+        }
+    }
+
+    fn paint(&mut self, display_list: &DisplayList) {
+        if self.width == 0 || self.height == 0 {
+            return;
+        }
         for item in display_list {
             self.paint_item(item);
         }
@@ -97,36 +412,63 @@ impl DebugCanvas {
         }
     }
 
+    /// Clamp the exclusive end of a horizontal range to the grid.
+    fn clamp_end_x(&self, x: i32) -> i32 {
+        x.clamp(0, self.width as i32)
+    }
+
+    /// Clamp the exclusive end of a vertical range to the grid.
+    fn clamp_end_y(&self, y: i32) -> i32 {
+        y.clamp(0, self.height as i32)
+    }
+
     fn paint_item(&mut self, item: &DisplayCommand) {
         match item {
-            DisplayCommand::Text(rect, text) => {
+            DisplayCommand::Text(rect, text, color) => {
+                let color = self.color(*color);
                 let x1 = self.clamp_x(rect.x) as usize;
                 let y = self.clamp_y(rect.y) as usize;
-                let x2 = self.clamp_x(rect.x + rect.width) as usize;
+                let x2 = self.clamp_end_x(rect.x + rect.width) as usize;
                 for (i, c) in text.chars().enumerate() {
                     let x = x1 + i;
                     if x >= x2 {
                         break;
                     }
-                    self.data[y][x] = c;
+                    self.data[y][x] = (c, color);
                 }
             }
+            DisplayCommand::FilledBox(_, _) if !self.fills => {}
             DisplayCommand::FilledBox(rect, bg) => {
                 let x0 = self.clamp_x(rect.x);
-                let x1 = self.clamp_x(rect.x + rect.width);
+                let x1 = self.clamp_end_x(rect.x + rect.width);
                 let y0 = self.clamp_y(rect.y);
-                let y1 = self.clamp_y(rect.y + rect.height);
+                let y1 = self.clamp_end_y(rect.y + rect.height);
                 for y in y0..y1 {
                     for x in x0..x1 {
-                        self.data[y as usize][x as usize] = *bg;
+                        self.data[y as usize][x as usize] = (*bg, None);
                     }
                 }
             }
-            DisplayCommand::BorderBox(rect, borders) => {
+            DisplayCommand::BorderBox(rect, borders, colors) => {
+                let (left, right) = (self.color(colors.left), self.color(colors.right));
+                let (top, bottom) = (self.color(colors.top), self.color(colors.bottom));
+                // Corners take the color of the side they continue when
+                // there is no top or bottom border.
+                let top_color = |side| match borders.top {
+                    Border::None => side,
+                    _ => top,
+                };
+                let bottom_color = |side| match borders.bottom {
+                    Border::None => side,
+                    _ => bottom,
+                };
                 let x0 = self.clamp_x(rect.x);
-                let x1 = self.clamp_x(rect.x + rect.width);
+                let x1 = self.clamp_end_x(rect.x + rect.width);
                 let y0 = self.clamp_y(rect.y);
-                let y1 = self.clamp_y(rect.y + rect.height);
+                let y1 = self.clamp_end_y(rect.y + rect.height);
+                if x1 <= x0 || y1 <= y0 {
+                    return;
+                }
 
                 // border characters
                 let top_border = match borders.top {
@@ -201,38 +543,139 @@ impl DebugCanvas {
 
                 if let Some(border) = top_border {
                     for x in (x0 + 1)..(x1 - 1) {
-                        self.data[y0 as usize][x as usize] = border;
+                        self.data[y0 as usize][x as usize] = (border, top);
                     }
                 }
                 if let Some(border) = bottom_border {
                     for x in (x0 + 1)..(x1 - 1) {
-                        self.data[(y1 - 1) as usize][x as usize] = border;
+                        self.data[(y1 - 1) as usize][x as usize] = (border, bottom);
                     }
                 }
                 if let Some(border) = left_border {
                     for y in (y0 + 1)..(y1 - 1) {
-                        self.data[y as usize][x0 as usize] = border;
+                        self.data[y as usize][x0 as usize] = (border, left);
                     }
                 }
                 if let Some(border) = right_border {
                     for y in (y0 + 1)..(y1 - 1) {
-                        self.data[y as usize][(x1 - 1) as usize] = border;
+                        self.data[y as usize][(x1 - 1) as usize] = (border, right);
                     }
                 }
 
                 if let Some(corner) = top_left {
-                    self.data[y0 as usize][x0 as usize] = corner;
+                    self.data[y0 as usize][x0 as usize] = (corner, top_color(left));
                 }
                 if let Some(corner) = top_right {
-                    self.data[y0 as usize][(x1 - 1) as usize] = corner;
+                    self.data[y0 as usize][(x1 - 1) as usize] = (corner, top_color(right));
                 }
                 if let Some(corner) = bottom_left {
-                    self.data[(y1 - 1) as usize][x0 as usize] = corner;
+                    self.data[(y1 - 1) as usize][x0 as usize] = (corner, bottom_color(left));
                 }
                 if let Some(corner) = bottom_right {
-                    self.data[(y1 - 1) as usize][(x1 - 1) as usize] = corner;
+                    self.data[(y1 - 1) as usize][(x1 - 1) as usize] = (corner, bottom_color(right));
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn text(x: i32, y: i32, text: &str) -> DisplayCommand<'_> {
+        let width = text.chars().count() as i32;
+        DisplayCommand::Text(
+            Rect {
+                x,
+                y,
+                width,
+                height: 1,
+            },
+            text,
+            None,
+        )
+    }
+
+    fn present(canvas: &mut TerminalCanvas, display_list: &DisplayList) -> String {
+        let mut out = Vec::new();
+        canvas.paint(display_list);
+        canvas.present(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn only_changed_cells_are_written() {
+        let mut canvas = TerminalCanvas::new(8, 2);
+        assert_eq!(
+            present(&mut canvas, &vec![text(0, 0, "hello"), text(0, 1, "world")]),
+            "\x1b[H\x1b[J\x1b[1;1Hhello   \x1b[2;1Hworld   \x1b[3;1H"
+        );
+        assert_eq!(
+            present(&mut canvas, &vec![text(0, 0, "hello"), text(0, 1, "world")]),
+            "\x1b[3;1H"
+        );
+        // Nearby changes are written together, distant ones separately.
+        assert_eq!(
+            present(
+                &mut canvas,
+                &vec![text(0, 0, "jelly"), text(0, 1, "w    rd")]
+            ),
+            "\x1b[1;1Hjelly\x1b[2;2H    rd\x1b[3;1H"
+        );
+
+        canvas.invalidate();
+        assert!(present(&mut canvas, &vec![]).starts_with("\x1b[H\x1b[J"));
+    }
+
+    #[test]
+    fn moved_rows_are_scrolled() {
+        let mut canvas = TerminalCanvas::new(4, 5);
+        let rows = ["head", "a", "b", "c", "d"];
+        let frame = |rows: &[&'static str]| {
+            rows.iter()
+                .enumerate()
+                .map(|(y, r)| text(0, y as i32, r))
+                .collect::<Vec<_>>()
+        };
+        present(&mut canvas, &frame(&rows));
+
+        // The rows under the header move up one, and a new row appears.
+        let out = present(&mut canvas, &frame(&["head", "b", "c", "d", "e"]));
+        assert_eq!(out, "\x1b[2;5r\x1b[1S\x1b[r\x1b[5;1He\x1b[6;1H");
+    }
+
+    #[test]
+    fn text_canvas_fits_the_document() {
+        use crate::content_tree::Document;
+        use crate::style::Stylesheet;
+        use crate::style_tree::style_tree;
+
+        let document = "<div class=\"block\"><p>Hello   world, and more</p><p>x</p></div>"
+            .parse::<Document>()
+            .unwrap();
+        let stylesheet = ".block { display: block; }\n\
+                          .p { display: block; border: light; width: 10; padding-left: 1; margin-bottom: 0; }"
+            .parse::<Stylesheet>()
+            .unwrap();
+        let styles = style_tree(&document, &stylesheet);
+        let mut tree = crate::layout::build_layout_tree(&document, &styles);
+        tree.layout(&document, &styles, &Dimensions::from_width(40));
+
+        let mut canvas = TextCanvas::for_layout(&tree);
+        canvas.paint(&build_display_list(&tree));
+        assert_eq!(
+            canvas.to_string(),
+            "┌────────┐\n\
+             │ Hello  │\n\
+             │ world, │\n\
+             │ and    │\n\
+             │ more   │\n\
+             └────────┘\n\
+             ┌────────┐\n\
+             │ x      │\n\
+             └────────┘\n"
+        );
+    }
+}
@@ -1,7 +1,10 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::content_tree::{Document, NodeId};
 use crate::style::*;
 use crate::style_tree::*;
 
-#[derive(Debug, Default, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
 pub struct Dimensions {
     pub border_box: Rect,
     pub padding: EdgeSizes,
@@ -17,7 +20,7 @@ impl Dimensions {
     }
 }
 
-#[derive(Debug, Default, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
 pub struct Rect {
     pub(crate) x: i32,
     pub(crate) y: i32,
@@ -25,7 +28,7 @@ pub struct Rect {
     pub(crate) height: i32,
 }
 
-#[derive(Debug, Default, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
 pub struct EdgeSizes {
     pub(crate) left: i32,
     pub(crate) right: i32,
@@ -33,7 +36,7 @@ pub struct EdgeSizes {
     pub(crate) bottom: i32,
 }
 
-#[derive(Debug, Default, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
 pub struct Borders {
     pub(crate) left: Border,
     pub(crate) right: Border,
@@ -41,6 +44,15 @@ pub struct Borders {
     pub(crate) bottom: Border,
 }
 
+/// The color of each of a box's borders, if it has one.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct BorderColors {
+    pub(crate) left: Option<Color>,
+    pub(crate) right: Option<Color>,
+    pub(crate) top: Option<Color>,
+    pub(crate) bottom: Option<Color>,
+}
+
 impl Borders {
     fn sizes(&self) -> EdgeSizes {
         EdgeSizes {
@@ -92,145 +104,570 @@ impl Rect {
     }
 }
 
-#[derive(Debug)]
-pub struct LayoutBox<'a> {
+/// A handle to a box in a [`LayoutTree`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BoxId(usize);
+
+/// The boxes generated for a document, stored in an arena so that each box
+/// knows its parent.
+#[derive(Debug, Clone)]
+pub struct LayoutTree {
+    boxes: Vec<LayoutBox>,
+    /// The box generated for each content node that has one.
+    node_boxes: HashMap<NodeId, BoxId>,
+    /// The box generated for each pseudo-element that has content.
+    pseudo_boxes: HashMap<(NodeId, PseudoElement), BoxId>,
+    /// The nodes whose styles generate content or change counters, which
+    /// other nodes' generated content may depend on.
+    generating: HashSet<NodeId>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LayoutBox {
     pub(crate) dimensions: Dimensions,
-    pub(crate) box_type: BoxType<'a>,
-    pub(crate) children: Vec<LayoutBox<'a>>,
+    pub(crate) box_type: BoxType,
+    pub(crate) parent: Option<BoxId>,
+    pub(crate) children: Vec<BoxId>,
+    /// For text boxes, the text placed on each line the box occupies.
+    pub(crate) fragments: Vec<TextFragment>,
+    /// For [`BoxType::GeneratedText`] and [`BoxType::Marker`] boxes, the text
+    /// to place.
+    pub(crate) text: Option<String>,
+    /// The color of the box's text.
+    pub(crate) color: Option<Color>,
+    pub(crate) border_colors: BorderColors,
+    /// Set when the box, or a box inside it, has changed since it was last
+    /// laid out.
+    dirty: bool,
+    /// The containing block the box was last laid out in. A box that isn't
+    /// dirty is only laid out again if this changes.
+    containing_block: Option<Dimensions>,
+}
+
+/// A run of text placed on a single line by inline layout.
+#[derive(Debug, Clone)]
+pub struct TextFragment {
+    pub(crate) rect: Rect,
+    pub(crate) text: String,
 }
 
-#[derive(Debug)]
-pub enum BoxType<'a> {
-    InlineNode(&'a StyledNode<'a>),
-    BlockNode(&'a StyledNode<'a>),
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoxType {
+    InlineNode(NodeId),
+    BlockNode(NodeId),
+    /// The box of a node's `::before` or `::after` pseudo-element.
+    InlinePseudo(NodeId, PseudoElement),
+    BlockPseudo(NodeId, PseudoElement),
+    /// The text of the `content` of the pseudo-element box containing it.
+    GeneratedText,
+    /// The marker of a list item. An `outside` marker is a child of the list
+    /// item's box that hangs to its left, and an `inside` one is placed like
+    /// inline content.
+    Marker(NodeId),
     Anonymous,
 }
 
-impl<'a> LayoutBox<'a> {
-    pub fn new(box_type: BoxType<'a>) -> LayoutBox<'a> {
+impl LayoutBox {
+    pub fn new(box_type: BoxType) -> LayoutBox {
         LayoutBox {
             dimensions: Dimensions::default(),
+            parent: None,
             children: Vec::new(),
+            fragments: Vec::new(),
+            text: None,
+            color: None,
+            border_colors: BorderColors::default(),
+            dirty: true,
+            containing_block: None,
             box_type,
         }
     }
-}
 
-pub fn build_layout_tree<'a>(styled_node: &'a StyledNode<'a>) -> LayoutBox<'a> {
-    let mut root = LayoutBox::new(match styled_node.display() {
-        DisplayKind::None => panic!("root has display: none;"),
-        DisplayKind::Inline => BoxType::InlineNode(styled_node),
-        DisplayKind::Block => BoxType::BlockNode(styled_node),
-    });
+    pub fn dimensions(&self) -> &Dimensions {
+        &self.dimensions
+    }
 
-    for child in &styled_node.children {
-        match child.display() {
-            DisplayKind::None => {}
-            DisplayKind::Block => root.children.push(build_layout_tree(child)),
-            DisplayKind::Inline => root
-                .get_inline_container()
-                .children
-                .push(build_layout_tree(child)),
+    pub fn box_type(&self) -> BoxType {
+        self.box_type
+    }
+
+    /// The content node this box was generated for, unless it is anonymous
+    /// or generated content.
+    pub fn node(&self) -> Option<NodeId> {
+        match self.box_type {
+            BoxType::InlineNode(node) | BoxType::BlockNode(node) => Some(node),
+            BoxType::InlinePseudo(..) | BoxType::BlockPseudo(..) => None,
+            BoxType::GeneratedText | BoxType::Marker(_) | BoxType::Anonymous => None,
         }
     }
 
-    root
+    pub fn parent(&self) -> Option<BoxId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[BoxId] {
+        &self.children
+    }
 }
 
-impl<'a> LayoutBox<'a> {
-    fn get_style_node(&self) -> &StyledNode<'a> {
-        match self.box_type {
-            BoxType::Anonymous => {
-                unimplemented!("Need to walk up the tree");
+/// Build the boxes for a styled document. The tree is empty if the root has
+/// `display: none`.
+pub fn build_layout_tree(document: &Document, styles: &StyleTree) -> LayoutTree {
+    let mut tree = LayoutTree {
+        boxes: Vec::new(),
+        node_boxes: HashMap::new(),
+        pseudo_boxes: HashMap::new(),
+        generating: HashSet::new(),
+    };
+    if styles[styles.root()].display() != DisplayKind::None {
+        let mut counters = Counters::default();
+        tree.build(document, styles, &mut counters, styles.root(), None);
+    }
+    tree
+}
+
+impl LayoutTree {
+    /// The box of the document's root, unless the tree is empty.
+    pub fn root(&self) -> Option<BoxId> {
+        if self.boxes.is_empty() {
+            None
+        } else {
+            Some(BoxId(0))
+        }
+    }
+
+    pub fn get(&self, id: BoxId) -> Option<&LayoutBox> {
+        self.boxes.get(id.0)
+    }
+
+    /// Add a box as the last child of `parent`.
+    fn push(&mut self, mut layout_box: LayoutBox, parent: Option<BoxId>) -> BoxId {
+        let id = BoxId(self.boxes.len());
+        layout_box.parent = parent;
+        self.boxes.push(layout_box);
+        if let Some(parent) = parent {
+            self.boxes[parent.0].children.push(id);
+        }
+        id
+    }
+
+    fn build(
+        &mut self,
+        document: &Document,
+        styles: &StyleTree,
+        counters: &mut Counters,
+        node: NodeId,
+        parent: Option<BoxId>,
+    ) -> BoxId {
+        let style = &styles[node];
+        let box_type = match style.display() {
+            DisplayKind::None => unreachable!("hidden nodes don't generate boxes"),
+            DisplayKind::Inline => BoxType::InlineNode(node),
+            DisplayKind::Block | DisplayKind::ListItem => BoxType::BlockNode(node),
+        };
+        let id = self.push(LayoutBox::new(box_type), parent);
+        self.node_boxes.insert(node, id);
+        if generates_content(styles, node) {
+            self.generating.insert(node);
+        }
+
+        update_counters(counters, style);
+        counters.enter();
+        if style.display() == DisplayKind::ListItem {
+            self.build_marker(styles, counters, node, id);
+        }
+        self.build_pseudo_element(document, styles, counters, node, PseudoElement::Before, id);
+        for &child in document[node].children() {
+            match styles[child].display() {
+                DisplayKind::None => {}
+                DisplayKind::Block | DisplayKind::ListItem => {
+                    self.build(document, styles, counters, child, Some(id));
+                }
+                DisplayKind::Inline => {
+                    let container = self.get_inline_container(id);
+                    self.build(document, styles, counters, child, Some(container));
+                }
             }
-            BoxType::InlineNode(s) | BoxType::BlockNode(s) => s,
         }
+        self.build_pseudo_element(document, styles, counters, node, PseudoElement::After, id);
+        counters.leave();
+
+        id
     }
 
-    fn get_inline_container(&mut self) -> &mut LayoutBox<'a> {
-        match self.box_type {
-            BoxType::Anonymous => self,
-            BoxType::InlineNode(_) => self,
-            BoxType::BlockNode(_) => {
+    /// Add the marker of a list item, numbered by its `list-item` counter.
+    fn build_marker(&mut self, styles: &StyleTree, counters: &Counters, node: NodeId, id: BoxId) {
+        let style = &styles[node];
+        let text = match style.list_style_type().marker(counters.value("list-item")) {
+            Some(text) => text,
+            None => return,
+        };
+        let parent = match style.list_style_position() {
+            ListStylePosition::Outside => id,
+            ListStylePosition::Inside => self.get_inline_container(id),
+        };
+        let mut marker = LayoutBox::new(BoxType::Marker(node));
+        marker.text = Some(text);
+        self.push(marker, Some(parent));
+    }
+
+    /// Add the box of a pseudo-element of `node`, if it has content, as the
+    /// last child of `parent`.
+    fn build_pseudo_element(
+        &mut self,
+        document: &Document,
+        styles: &StyleTree,
+        counters: &mut Counters,
+        node: NodeId,
+        pseudo_element: PseudoElement,
+        parent: BoxId,
+    ) {
+        let style = match styles.pseudo_element(node, pseudo_element) {
+            Some(style) => style,
+            None => return,
+        };
+        let items = match style.value("content") {
+            Some(Value::Content(items)) => items,
+            _ => return,
+        };
+        let (id, container) = match style.display() {
+            DisplayKind::None => return,
+            DisplayKind::Block | DisplayKind::ListItem => {
+                let block = BoxType::BlockPseudo(node, pseudo_element);
+                let id = self.push(LayoutBox::new(block), Some(parent));
+                (id, self.push(LayoutBox::new(BoxType::Anonymous), Some(id)))
+            }
+            DisplayKind::Inline => {
+                let container = self.get_inline_container(parent);
+                let inline = BoxType::InlinePseudo(node, pseudo_element);
+                let id = self.push(LayoutBox::new(inline), Some(container));
+                (id, id)
+            }
+        };
+        self.pseudo_boxes.insert((node, pseudo_element), id);
+
+        // Pseudo-elements are children of their element, so the counters
+        // they reset are in scope for the rest of its children.
+        update_counters(counters, style);
+        let element = document[node].element_data();
+        let text = items
+            .iter()
+            .map(|item| match item {
+                ContentItem::Text(text) => text.clone(),
+                ContentItem::Attr(name) => element
+                    .and_then(|e| e.attribute(name))
+                    .unwrap_or_default()
+                    .to_string(),
+                ContentItem::Counter(name, style) => style.format(counters.value(name)),
+                ContentItem::Counters(name, separator, style) => {
+                    let mut values = counters.values(name);
+                    if values.is_empty() {
+                        values.push(0);
+                    }
+                    let values = values.into_iter().map(|v| style.format(v));
+                    values.collect::<Vec<_>>().join(separator)
+                }
+            })
+            .collect();
+        let mut text_box = LayoutBox::new(BoxType::GeneratedText);
+        text_box.text = Some(text);
+        self.push(text_box, Some(container));
+    }
+
+    fn get_inline_container(&mut self, id: BoxId) -> BoxId {
+        match self[id].box_type {
+            BoxType::Anonymous | BoxType::GeneratedText | BoxType::Marker(_) => id,
+            BoxType::InlineNode(_) | BoxType::InlinePseudo(..) => id,
+            BoxType::BlockNode(_) | BoxType::BlockPseudo(..) => {
                 // If we just added a new anonymous box, keep using it.  Otherwise,
                 // add a new one.
-                match self.children.last() {
-                    Some(&LayoutBox {
-                        box_type: BoxType::Anonymous,
-                        ..
-                    }) => {}
-                    _ => self.children.push(LayoutBox::new(BoxType::Anonymous)),
+                match self[id].children.last() {
+                    Some(&last) if self[last].box_type == BoxType::Anonymous => last,
+                    _ => self.push(LayoutBox::new(BoxType::Anonymous), Some(id)),
                 }
-                self.children.last_mut().expect("we just added a box")
             }
         }
     }
 
-    pub fn layout(&mut self, containing_block: &Dimensions) {
-        match self.box_type {
+    /// The box generated for a content node, if it has one.
+    pub fn node_box(&self, node: NodeId) -> Option<BoxId> {
+        self.node_boxes.get(&node).copied()
+    }
+
+    /// The box generated for a pseudo-element of `node`, if it has one.
+    pub fn pseudo_box(&self, node: NodeId, pseudo_element: PseudoElement) -> Option<BoxId> {
+        self.pseudo_boxes.get(&(node, pseudo_element)).copied()
+    }
+
+    /// Update the tree for content nodes whose styles or contents changed,
+    /// such as those returned by [`StyleTree::restyle`].
+    ///
+    /// Their boxes are laid out again by the next call to
+    /// [`LayoutTree::layout`], along with the boxes containing them. If any
+    /// node's `display` changed, its children were inserted, removed or
+    /// moved, or a node generates content or changes counters, the whole tree
+    /// is rebuilt.
+    pub fn restyle(&mut self, document: &Document, styles: &StyleTree, nodes: &[NodeId]) {
+        if nodes.iter().any(|&node| {
+            self.box_type_changed(document, styles, node)
+                || self.children_changed(document, node)
+                || self.generating.contains(&node)
+                || generates_content(styles, node)
+        }) {
+            *self = build_layout_tree(document, styles);
+            return;
+        }
+        for node in nodes {
+            let mut next = self.node_box(*node);
+            while let Some(id) = next {
+                let layout_box = &mut self.boxes[id.0];
+                if layout_box.dirty {
+                    // Its ancestors were marked along with it.
+                    break;
+                }
+                layout_box.dirty = true;
+                next = layout_box.parent;
+            }
+        }
+    }
+
+    /// Whether the box a node should generate differs from the one it has.
+    fn box_type_changed(&self, document: &Document, styles: &StyleTree, node: NodeId) -> bool {
+        let displayed = match document[node].parent() {
+            Some(parent) => self.node_box(parent).is_some(),
+            None => true,
+        };
+        let expected = match styles[node].display() {
+            _ if !displayed => None,
+            DisplayKind::None => None,
+            DisplayKind::Inline => Some(BoxType::InlineNode(node)),
+            DisplayKind::Block | DisplayKind::ListItem => Some(BoxType::BlockNode(node)),
+        };
+        expected != self.node_box(node).map(|id| self[id].box_type)
+    }
+
+    /// Whether the boxes of a node's children no longer match its children in
+    /// the document. Children that are missing a box are found by
+    /// [`LayoutTree::box_type_changed`] instead.
+    fn children_changed(&self, document: &Document, node: NodeId) -> bool {
+        let id = match self.node_box(node) {
+            Some(id) => id,
+            None => return false,
+        };
+        let mut boxed = Vec::new();
+        self.child_nodes(id, &mut boxed);
+        let expected = document[node]
+            .children()
+            .iter()
+            .copied()
+            .filter(|&child| self.node_box(child).is_some());
+        !boxed.into_iter().eq(expected)
+    }
+
+    /// The nodes whose boxes are children of `id`, including those inside
+    /// anonymous boxes.
+    fn child_nodes(&self, id: BoxId, nodes: &mut Vec<NodeId>) {
+        for &child in &self[id].children {
+            match self[child].box_type {
+                BoxType::InlineNode(node) | BoxType::BlockNode(node) => nodes.push(node),
+                BoxType::Anonymous => self.child_nodes(child, nodes),
+                _ => {}
+            }
+        }
+    }
+
+    /// Lay out the tree within `containing_block`, using the document and
+    /// styles it was built from.
+    ///
+    /// Boxes that haven't changed since the last layout, and whose containing
+    /// block is the same, keep their dimensions. Returns the number of boxes
+    /// that were laid out.
+    pub fn layout(
+        &mut self,
+        document: &Document,
+        styles: &StyleTree,
+        containing_block: &Dimensions,
+    ) -> usize {
+        let root = match self.root() {
+            Some(root) => root,
+            None => return 0,
+        };
+        let mut context = LayoutContext {
+            document,
+            styles,
+            boxes: &mut self.boxes,
+            laid_out: 0,
+        };
+        context.layout(root, containing_block);
+        context.laid_out
+    }
+}
+
+/// Whether a node has pseudo-elements with content, is a list item, or
+/// changes counters. Generated content depends on the node's attributes and
+/// on counters throughout the document, so it is generated again rather than
+/// updated when any of these nodes are restyled.
+fn generates_content(styles: &StyleTree, node: NodeId) -> bool {
+    let style = &styles[node];
+    PseudoElement::ALL
+        .iter()
+        .any(|&pseudo_element| styles.pseudo_element(node, pseudo_element).is_some())
+        || style.display() == DisplayKind::ListItem
+        || !style.counters("counter-reset").is_empty()
+        || !style.counters("counter-increment").is_empty()
+}
+
+/// Apply the `counter-reset` and `counter-increment` of a node or
+/// pseudo-element. List items also increment the `list-item` counter, unless
+/// they increment it themselves.
+fn update_counters(counters: &mut Counters, style: &StyledNode) {
+    let mut increment = style.counters("counter-increment");
+    if style.display() == DisplayKind::ListItem
+        && !increment.iter().any(|(name, _)| name == "list-item")
+    {
+        increment.push((String::from("list-item"), 1));
+    }
+    counters.update(&style.counters("counter-reset"), &increment);
+}
+
+impl std::ops::Index<BoxId> for LayoutTree {
+    type Output = LayoutBox;
+
+    fn index(&self, id: BoxId) -> &LayoutBox {
+        &self.boxes[id.0]
+    }
+}
+
+/// The state needed while laying out the boxes of a [`LayoutTree`].
+struct LayoutContext<'a> {
+    document: &'a Document,
+    styles: &'a StyleTree,
+    boxes: &'a mut Vec<LayoutBox>,
+    /// The number of boxes laid out so far.
+    laid_out: usize,
+}
+
+impl<'a> LayoutContext<'a> {
+    fn get_style_node(&self, mut id: BoxId) -> &'a StyledNode {
+        // Anonymous boxes take their style from the nearest box that has one.
+        loop {
+            let layout_box = &self.boxes[id.0];
+            match layout_box.box_type {
+                BoxType::InlineNode(node) | BoxType::BlockNode(node) | BoxType::Marker(node) => {
+                    return &self.styles[node]
+                }
+                BoxType::InlinePseudo(node, pseudo_element)
+                | BoxType::BlockPseudo(node, pseudo_element) => {
+                    return self
+                        .styles
+                        .pseudo_element(node, pseudo_element)
+                        .expect("pseudo-element boxes are only built for styled content");
+                }
+                BoxType::GeneratedText | BoxType::Anonymous => {
+                    id = layout_box.parent.expect("the root box is never anonymous")
+                }
+            }
+        }
+    }
+
+    /// The text a box places, if it is a text node, generated text or a
+    /// marker.
+    fn text(&self, id: BoxId) -> Option<&str> {
+        let layout_box = &self.boxes[id.0];
+        match layout_box.box_type {
+            BoxType::InlineNode(node) => self.document[node].text(),
+            BoxType::GeneratedText | BoxType::Marker(_) => layout_box.text.as_deref(),
+            _ => None,
+        }
+    }
+
+    fn layout(&mut self, id: BoxId, containing_block: &Dimensions) {
+        let layout_box = &self.boxes[id.0];
+        if !layout_box.dirty && layout_box.containing_block.as_ref() == Some(containing_block) {
+            return;
+        }
+        self.laid_out += 1;
+
+        match self.boxes[id.0].box_type {
             BoxType::Anonymous => {
-                self.dimensions = containing_block.clone();
-                let span = tracing::info_span!("anonymous layout", ?self.dimensions);
+                let span = tracing::info_span!("anonymous layout", ?containing_block);
                 let _e = span.enter();
                 tracing::info!("starting anonymous layout...");
-                self.layout_inline_children();
-                // fix this when doing text runs
-                self.dimensions.border_box.height = 1;
+                self.layout_anonymous(id, containing_block);
                 tracing::info!("finished anonymous layout");
             }
-            BoxType::InlineNode(_) => {
-                self.layout_inline(containing_block);
+            BoxType::InlineNode(_) | BoxType::InlinePseudo(..) | BoxType::GeneratedText => {
+                self.layout_inline(id, containing_block);
             }
-            BoxType::BlockNode(_) => {
-                self.layout_block(containing_block);
+            BoxType::BlockNode(_) | BoxType::BlockPseudo(..) => {
+                self.layout_block(id, containing_block);
+            }
+            BoxType::Marker(_) => {
+                self.layout_marker(id, containing_block);
             }
         }
+
+        self.set_colors(id);
+        let layout_box = &mut self.boxes[id.0];
+        layout_box.dirty = false;
+        layout_box.containing_block = Some(*containing_block);
+    }
+
+    /// Record the colors the box is drawn in.
+    fn set_colors(&mut self, id: BoxId) {
+        let style = self.get_style_node(id);
+        let layout_box = &mut self.boxes[id.0];
+        layout_box.color = style.color();
+        layout_box.border_colors = BorderColors {
+            left: style.border_color("border-left-color"),
+            right: style.border_color("border-right-color"),
+            top: style.border_color("border-top-color"),
+            bottom: style.border_color("border-bottom-color"),
+        };
     }
 
-    fn layout_block(&mut self, containing_block: &Dimensions) {
+    fn layout_block(&mut self, id: BoxId, containing_block: &Dimensions) {
         // Child width can depend on parent width, so we need to calculate
         // this box's width before laying out its children.
-        self.calculate_block_width(containing_block);
+        self.calculate_block_width(id, containing_block);
 
         // Determine where the box is located within its container.
-        self.calculate_block_position(containing_block);
+        self.calculate_block_position(id, containing_block);
 
         // Recursively lay out the children of this box.
-        self.layout_block_children();
+        self.layout_block_children(id);
 
         // Parent height can depend on child height, so `calculate_height`
         // must be called *after* the children are laid out.
-        self.calculate_block_height();
+        self.calculate_block_height(id);
     }
 
-    fn calculate_block_width(&mut self, containing_block: &Dimensions) {
+    fn calculate_block_width(&mut self, id: BoxId, containing_block: &Dimensions) {
         use Value::{AbsoluteLength, Auto};
         let zero = AbsoluteLength(0);
 
-        let style = self.get_style_node();
+        let style = self.get_style_node(id);
 
         let mut width = style.value("width").unwrap_or(Auto);
 
-        let mut margin_left = style.lookup("margin-left", "margin", &zero).clone();
-        let mut margin_right = style.lookup("margin-right", "margin", &zero).clone();
+        let mut margin_left = style.lookup("margin-left", &zero).clone();
+        let mut margin_right = style.lookup("margin-right", &zero).clone();
 
-        let border_left = if let Value::Border(b) =
-            style.lookup("border-left", "border", &Value::Border(Border::None))
-        {
-            b
-        } else {
-            Border::None
-        };
-        let border_right = if let Value::Border(b) =
-            style.lookup("border-right", "border", &Value::Border(Border::None))
-        {
-            b
-        } else {
-            Border::None
-        };
+        let border_left =
+            if let Value::Border(b) = style.lookup("border-left", &Value::Border(Border::None)) {
+                b
+            } else {
+                Border::None
+            };
+        let border_right =
+            if let Value::Border(b) = style.lookup("border-right", &Value::Border(Border::None)) {
+                b
+            } else {
+                Border::None
+            };
 
-        let padding_left = style.lookup("padding-left", "padding", &zero);
-        let padding_right = style.lookup("padding-right", "padding", &zero);
+        let padding_left = style.lookup("padding-left", &zero);
+        let padding_right = style.lookup("padding-right", &zero);
 
         let total = [&margin_left, &margin_right, &width]
             .iter()
@@ -294,7 +731,7 @@ impl<'a> LayoutBox<'a> {
             }
         }
 
-        let d = &mut self.dimensions;
+        let d = &mut self.boxes[id.0].dimensions;
 
         d.border_box.width = width.to_chars();
         d.padding.left = padding_left.to_chars();
@@ -305,42 +742,34 @@ impl<'a> LayoutBox<'a> {
         d.border.right = border_right;
     }
 
-    fn calculate_block_position(&mut self, containing_block: &Dimensions) {
+    fn calculate_block_position(&mut self, id: BoxId, containing_block: &Dimensions) {
         use Value::AbsoluteLength;
 
-        let d = &mut self.dimensions;
-        //let style = self.get_style_node();
-        let style = match self.box_type {
-            BoxType::Anonymous => {
-                unimplemented!("Need to walk up the tree");
-            }
-            BoxType::InlineNode(s) | BoxType::BlockNode(s) => s,
-        };
+        let style = self.get_style_node(id);
+        let d = &mut self.boxes[id.0].dimensions;
 
         // margin, border, and padding have initial value 0.
         let zero = AbsoluteLength(0);
 
         // If margin-top or margin-bottom is `auto`, the used value is zero.
-        d.margin.top = style.lookup("margin-top", "margin", &zero).to_chars();
-        d.margin.bottom = style.lookup("margin-bottom", "margin", &zero).to_chars();
+        d.margin.top = style.lookup("margin-top", &zero).to_chars();
+        d.margin.bottom = style.lookup("margin-bottom", &zero).to_chars();
 
-        d.border.top = if let Value::Border(b) =
-            style.lookup("border-top", "border", &Value::Border(Border::None))
-        {
-            b
-        } else {
-            Border::None
-        };
-        d.border.bottom = if let Value::Border(b) =
-            style.lookup("border-bottom", "border", &Value::Border(Border::None))
-        {
-            b
-        } else {
-            Border::None
-        };
+        d.border.top =
+            if let Value::Border(b) = style.lookup("border-top", &Value::Border(Border::None)) {
+                b
+            } else {
+                Border::None
+            };
+        d.border.bottom =
+            if let Value::Border(b) = style.lookup("border-bottom", &Value::Border(Border::None)) {
+                b
+            } else {
+                Border::None
+            };
 
-        d.padding.top = style.lookup("padding-top", "padding", &zero).to_chars();
-        d.padding.bottom = style.lookup("padding-bottom", "padding", &zero).to_chars();
+        d.padding.top = style.lookup("padding-top", &zero).to_chars();
+        d.padding.bottom = style.lookup("padding-bottom", &zero).to_chars();
         // Initialize the height with the size of the vertical padding and border.
         d.border_box.height =
             d.padding.top + d.padding.bottom + d.border.top.size() + d.border.bottom.size();
@@ -348,90 +777,542 @@ impl<'a> LayoutBox<'a> {
         d.border_box.x = containing_block.content_box().x + d.margin.left;
 
         // Position the box below all the previous boxes in the container.
-        println!(
-            "containing content\n\t{:?},\nd.margin\n\t{:?}",
-            containing_block.content_box(),
-            d.margin
-        );
+        tracing::debug!(containing = ?containing_block.content_box(), ?d.margin);
         d.border_box.y =
             containing_block.content_box().y + containing_block.content_box().height + d.margin.top;
     }
 
-    fn layout_block_children(&mut self) {
-        let d = &mut self.dimensions;
-        for child in &mut self.children {
-            child.layout(d);
+    fn layout_block_children(&mut self, id: BoxId) {
+        for child in self.boxes[id.0].children.clone() {
+            let d = self.boxes[id.0].dimensions;
+            self.layout(child, &d);
+            if let BoxType::Marker(_) = self.boxes[child.0].box_type {
+                // Outside markers hang beside the content, taking no room.
+                continue;
+            }
             // Track the height so each child is laid out below the previous content
-            let height = child.dimensions.margin_box().height;
+            let height = self.boxes[child.0].dimensions.margin_box().height;
             tracing::debug!(height, "adding");
-            d.border_box.height += height;
+            self.boxes[id.0].dimensions.border_box.height += height;
         }
     }
 
-    fn calculate_block_height(&mut self) {
+    fn calculate_block_height(&mut self, id: BoxId) {
         // If the height is set to an explicit length, use that exact length.
         // Otherwise, the height is the size set by `layout_block_children`.
-        if let Some(Value::AbsoluteLength(h)) = self.get_style_node().value("height") {
-            self.dimensions.border_box.height = h;
+        let style = self.get_style_node(id);
+        if let Some(Value::AbsoluteLength(h)) = style.value("height") {
+            let d = &mut self.boxes[id.0].dimensions;
+            let extra = h - d.border_box.height;
+            d.border_box.height = h;
+
+            // Content that doesn't fill the height is placed according to
+            // `align-content`. Content that overflows stays at the top.
+            let distance = match style.align_content() {
+                _ if extra <= 0 => return,
+                AlignContent::Start => return,
+                AlignContent::Center => extra / 2,
+                AlignContent::End => extra,
+            };
+            for child in self.boxes[id.0].children.clone() {
+                self.move_down(child, distance);
+            }
+        }
+    }
+
+    /// Move a box and everything inside it down by `distance` rows, as if it
+    /// had been laid out in a containing block that much lower.
+    fn move_down(&mut self, id: BoxId, distance: i32) {
+        let mut stack = vec![id];
+        while let Some(b) = stack.pop() {
+            let layout_box = &mut self.boxes[b.0];
+            layout_box.dimensions.border_box.y += distance;
+            for fragment in &mut layout_box.fragments {
+                fragment.rect.y += distance;
+            }
+            // Keep the box from being reused in place if its container is
+            // laid out again from the top.
+            if let Some(containing_block) = &mut layout_box.containing_block {
+                containing_block.border_box.y += distance;
+            }
+            stack.extend(layout_box.children.iter().copied());
         }
     }
 
-    fn layout_inline(&mut self, containing_block: &Dimensions) {
+    /// Place an `outside` list marker on the first line of its list item,
+    /// ending where the item's content starts.
+    fn layout_marker(&mut self, id: BoxId, containing_block: &Dimensions) {
+        let content = containing_block.content_box();
+        let layout_box = &mut self.boxes[id.0];
+        let text = layout_box.text.clone().unwrap_or_default();
+        let width = text.chars().count() as i32;
+        let rect = Rect {
+            x: content.x - width,
+            y: content.y + content.height,
+            width,
+            height: 1,
+        };
+        layout_box.fragments = vec![TextFragment { rect, text }];
+        layout_box.dimensions = Dimensions::default();
+        layout_box.dimensions.border_box = rect;
+    }
+
+    /// Lay out an anonymous block box, which holds a run of inline content
+    /// inside a block container.
+    fn layout_anonymous(&mut self, id: BoxId, containing_block: &Dimensions) {
+        let content = containing_block.content_box();
+        let d = &mut self.boxes[id.0].dimensions;
+        *d = Dimensions::default();
+        d.border_box.x = content.x;
+        d.border_box.y = content.y + content.height;
+        d.border_box.width = content.width;
+
+        let mut lines = LineBreaker::new(d.content_box());
+        for child in self.boxes[id.0].children.clone() {
+            lines.flow(self, child);
+        }
+        self.align_lines(id, &lines);
+        self.boxes[id.0].dimensions.border_box.height = lines.height();
+    }
+
+    /// Lay out an inline box that is not inside an inline formatting context
+    /// of its own, i.e. the root of the layout tree.
+    fn layout_inline(&mut self, id: BoxId, containing_block: &Dimensions) {
         let span = tracing::info_span!("layout inline", ?containing_block);
         let _e = span.enter();
-        tracing::debug!("calculating inline position...");
-        self.calculate_inline_position(containing_block);
 
-        tracing::debug!("laying out inline children...");
-        self.layout_inline_children();
+        let (margin_left, margin_right, padding_left, padding_right) = self.inline_edges(id);
+        let content = containing_block.content_box();
+        let style = self.get_style_node(id);
+        let layout_box = &mut self.boxes[id.0];
+        let d = &mut layout_box.dimensions;
+        d.margin.left = margin_left;
+        d.margin.right = margin_right;
+        d.padding.left = padding_left;
+        d.padding.right = padding_right;
+        d.border_box.x = content.x + margin_left;
+        d.border_box.y = content.y + content.height;
+        d.border_box.width = content.width - margin_left - margin_right;
 
-        tracing::debug!("calculating inline width...");
-        self.calculate_inline_width(containing_block);
+        let mut lines = LineBreaker::new(d.content_box());
+        let mut fragments = Vec::new();
+        if let Some(text) = self.text(id) {
+            lines.place_text(text, style.white_space(), &mut fragments);
+        }
+        self.boxes[id.0].fragments = fragments;
+        for child in self.boxes[id.0].children.clone() {
+            lines.flow(self, child);
+        }
+        self.align_lines(id, &lines);
+        let d = &mut self.boxes[id.0].dimensions;
+        d.border_box.height = lines.height();
+        tracing::debug!(?d.border_box, "laid out inline root");
+    }
+
+    /// Move the content of each line that `lines` placed in the inline
+    /// formatting context of `id` according to its `text-align`.
+    fn align_lines(&mut self, id: BoxId, lines: &LineBreaker) {
+        let align = self.get_style_node(id).text_align();
+        if align == TextAlign::Left {
+            return;
+        }
+        let boxes = self.inline_boxes(id);
+        let bounds = lines.bounds;
+        let shifts = (0..lines.lines)
+            .map(|line| {
+                let y = bounds.y + line as i32;
+                let mut text = boxes
+                    .iter()
+                    .flat_map(|&b| &self.boxes[b.0].fragments)
+                    .filter(|f| f.rect.y == y)
+                    .flat_map(|f| (f.rect.x..).zip(f.text.chars()))
+                    .collect::<Vec<_>>();
+                text.sort_by_key(|&(x, _)| x);
+                let end = match text.last() {
+                    Some(&(x, _)) => x + 1,
+                    None => return LineShifts::default(),
+                };
+                let extra = bounds.x + bounds.width - end;
+                match align {
+                    _ if extra <= 0 => LineShifts::default(),
+                    TextAlign::Left => LineShifts::default(),
+                    TextAlign::Right => LineShifts::by(extra),
+                    TextAlign::Center => LineShifts::by(extra / 2),
+                    TextAlign::Justify if lines.wrapped.contains(&line) => {
+                        LineShifts::justify(&text, extra)
+                    }
+                    TextAlign::Justify => LineShifts::default(),
+                }
+            })
+            .collect::<Vec<_>>();
+        let line_shifts = |y: i32| shifts.get((y - bounds.y) as usize);
+
+        for b in boxes {
+            let has_text = self.text(b).is_some();
+            let layout_box = &mut self.boxes[b.0];
+            for fragment in &mut layout_box.fragments {
+                if let Some(shifts) = line_shifts(fragment.rect.y) {
+                    shifts.apply(fragment);
+                }
+            }
+            if b == id {
+                // The container keeps its own dimensions.
+                continue;
+            }
+            let d = &mut layout_box.dimensions;
+            if has_text {
+                d.border_box = fragments_bounds(&layout_box.fragments);
+            } else if let Some(shifts) = line_shifts(d.border_box.y) {
+                let start = d.border_box.x;
+                let end = start + d.border_box.width;
+                let moved = shifts.at(start);
+                d.border_box.x += moved;
+                if d.border_box.height == 1 && end > start {
+                    d.border_box.width += shifts.at(end - 1) - moved;
+                }
+            }
+        }
     }
 
-    fn calculate_inline_position(&mut self, containing_block: &Dimensions) {
-        let style = self.get_style_node();
+    /// The boxes in the inline formatting context of `id`, including `id`
+    /// itself, but not blocks inside it or their contents.
+    fn inline_boxes(&self, id: BoxId) -> Vec<BoxId> {
+        let mut boxes = vec![id];
+        let mut i = 0;
+        while let Some(&b) = boxes.get(i) {
+            i += 1;
+            let inline = self.boxes[b.0].children.iter().filter(|&&c| {
+                !matches!(
+                    self.boxes[c.0].box_type,
+                    BoxType::BlockNode(_) | BoxType::BlockPseudo(..) | BoxType::Anonymous
+                )
+            });
+            boxes.extend(inline);
+        }
+        boxes
+    }
 
+    /// The horizontal margins and padding of an inline box.
+    fn inline_edges(&self, id: BoxId) -> (i32, i32, i32, i32) {
         use Value::AbsoluteLength;
         let zero = AbsoluteLength(0);
+        let style = self.get_style_node(id);
+        (
+            style.lookup("margin-left", &zero).to_chars(),
+            style.lookup("margin-right", &zero).to_chars(),
+            style.lookup("padding-left", &zero).to_chars(),
+            style.lookup("padding-right", &zero).to_chars(),
+        )
+    }
+}
 
-        let margin_left = style.lookup("margin-left", "margin", &zero).to_chars();
-        let margin_right = style.lookup("margin-right", "margin", &zero).to_chars();
-        let padding_left = style.lookup("padding-left", "padding", &zero).to_chars();
-        let padding_right = style.lookup("padding-right", "padding", &zero).to_chars();
+/// Places inline content into line boxes, breaking lines according to the
+/// `white-space` property of each text node.
+struct LineBreaker {
+    /// The area lines are placed in. Its height is ignored.
+    bounds: Rect,
+    /// The index of the line currently being filled.
+    line: usize,
+    /// The position on the current line, relative to `bounds.x`.
+    x: i32,
+    /// Set when a collapsible space should precede the next word.
+    pending_space: bool,
+    /// The number of lines that contain content.
+    lines: usize,
+    /// The lines that were broken to fit the width, rather than by a newline
+    /// or a block, which justified text is spread across.
+    wrapped: Vec<usize>,
+}
 
-        let d = &mut self.dimensions;
-        d.margin.left = margin_left;
-        d.margin.right = margin_right;
-        d.padding.left = padding_left;
-        d.padding.right = padding_right;
+impl LineBreaker {
+    fn new(bounds: Rect) -> Self {
+        LineBreaker {
+            bounds,
+            line: 0,
+            x: 0,
+            pending_space: false,
+            lines: 0,
+            wrapped: Vec::new(),
+        }
+    }
 
-        // Inline elements have height 1 (?)
-        d.border_box.height = 1;
-        d.border_box.width = d.padding.left + d.padding.right;
-        d.border_box.x = containing_block.content_box().x + d.margin.left;
-        d.border_box.y = containing_block.content_box().y + containing_block.content_box().height;
-        tracing::debug!(?d.border_box, "calculated inline position");
+    /// The total height of the lines placed so far.
+    fn height(&self) -> i32 {
+        self.lines as i32
+    }
+
+    fn break_line(&mut self) {
+        self.line += 1;
+        self.x = 0;
+        self.pending_space = false;
+    }
+
+    /// Break the line because the next content doesn't fit on it.
+    fn wrap_line(&mut self) {
+        self.wrapped.push(self.line);
+        self.break_line();
     }
 
-    fn layout_inline_children(&mut self) {
-        let mut left_space = self.dimensions.clone();
-        for child in &mut self.children {
-            child.layout(&mut left_space);
-            // Move to the left so that each child is laid out after
-            // the previous children. TODO; line breaks.
-            let child_width = child.dimensions.margin_box().width;
-            tracing::debug!(child.width = child_width, "laid out child, adding its width");
-            left_space.border_box.x += child_width;
-            self.dimensions.border_box.width += child_width;
+    /// Place a box and its descendants onto lines.
+    fn flow(&mut self, context: &mut LayoutContext, id: BoxId) {
+        match context.boxes[id.0].box_type {
+            BoxType::InlineNode(_)
+            | BoxType::InlinePseudo(..)
+            | BoxType::GeneratedText
+            | BoxType::Marker(_) => {
+                // Inline boxes are always placed again along with the rest
+                // of their line boxes.
+                context.laid_out += 1;
+                context.boxes[id.0].dirty = false;
+                context.set_colors(id);
+
+                let style = context.get_style_node(id);
+                if let Some(text) = context.text(id) {
+                    let mut fragments = Vec::new();
+                    self.place_text(text, style.white_space(), &mut fragments);
+                    let layout_box = &mut context.boxes[id.0];
+                    layout_box.fragments = fragments;
+                    layout_box.dimensions = Dimensions::default();
+                    layout_box.dimensions.border_box = fragments_bounds(&layout_box.fragments);
+                    return;
+                }
+
+                let (margin_left, margin_right, padding_left, padding_right) =
+                    context.inline_edges(id);
+                let (start_line, start_x) = (self.line, self.x);
+                self.x += margin_left + padding_left;
+                for child in context.boxes[id.0].children.clone() {
+                    self.flow(context, child);
+                }
+                self.x += padding_right + margin_right;
+
+                let d = &mut context.boxes[id.0].dimensions;
+                d.margin.left = margin_left;
+                d.margin.right = margin_right;
+                d.padding.left = padding_left;
+                d.padding.right = padding_right;
+                d.border_box.x = self.bounds.x + start_x + margin_left;
+                d.border_box.y = self.bounds.y + start_line as i32;
+                d.border_box.height = (self.line - start_line) as i32 + 1;
+                d.border_box.width = if self.line == start_line {
+                    self.x - start_x - margin_left - margin_right
+                } else {
+                    self.bounds.width - start_x - margin_left
+                };
+            }
+            BoxType::BlockNode(_) | BoxType::BlockPseudo(..) | BoxType::Anonymous => {
+                // A block inside inline content gets lines of its own.
+                if self.x > 0 {
+                    self.break_line();
+                }
+                let containing_block = Dimensions {
+                    border_box: Rect {
+                        height: self.line as i32,
+                        ..self.bounds
+                    },
+                    ..Default::default()
+                };
+                context.layout(id, &containing_block);
+                let height = context.boxes[id.0].dimensions.margin_box().height;
+                self.line += height.max(0) as usize;
+                self.lines = self.line;
+                self.x = 0;
+                self.pending_space = false;
+            }
         }
     }
 
-    fn calculate_inline_width(&mut self, containing_block: &Dimensions) {
-        // If this is a text element, add the length of the string.
-        // Otherwise, the width is computed by `layout_inline_children`.
-        if let Some(text) = self.get_style_node().node().text() {
-            self.dimensions.border_box.width += text.chars().count() as i32;
+    /// Place the contents of a text node, adding a fragment for each line it
+    /// occupies.
+    fn place_text(
+        &mut self,
+        text: &str,
+        white_space: WhiteSpace,
+        fragments: &mut Vec<TextFragment>,
+    ) {
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            if c == '\n' && white_space.preserves_newlines() {
+                self.break_line();
+                rest = &rest[1..];
+            } else if c.is_whitespace() {
+                let end = rest
+                    .find(|c: char| {
+                        !c.is_whitespace() || (c == '\n' && white_space.preserves_newlines())
+                    })
+                    .unwrap_or(rest.len());
+                if white_space.collapses_spaces() {
+                    if self.x > 0 {
+                        self.pending_space = true;
+                    }
+                } else {
+                    self.place_spaces(&rest[..end], white_space, fragments);
+                }
+                rest = &rest[end..];
+            } else {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                self.place_word(&rest[..end], white_space, fragments);
+                rest = &rest[end..];
+            }
         }
     }
+
+    fn place_word(
+        &mut self,
+        word: &str,
+        white_space: WhiteSpace,
+        fragments: &mut Vec<TextFragment>,
+    ) {
+        let width = word.chars().count() as i32;
+        let space = if self.pending_space { 1 } else { 0 };
+        if white_space.wraps() && self.x > 0 && self.x + space + width > self.bounds.width {
+            self.wrap_line();
+        } else if self.pending_space {
+            self.push_text(" ", fragments);
+        }
+        self.push_text(word, fragments);
+    }
+
+    /// Place a run of preserved whitespace, expanding tabs to the next
+    /// multiple of eight columns.
+    fn place_spaces(
+        &mut self,
+        spaces: &str,
+        white_space: WhiteSpace,
+        fragments: &mut Vec<TextFragment>,
+    ) {
+        if self.pending_space {
+            self.push_text(" ", fragments);
+        }
+        let mut expanded = String::new();
+        for c in spaces.chars() {
+            let x = self.x + expanded.len() as i32;
+            match c {
+                '\t' => expanded.push_str(&" ".repeat(8 - (x % 8) as usize)),
+                _ => expanded.push(' '),
+            }
+        }
+        // Preserved spaces that would overflow a wrapping line hang off its end.
+        if white_space.wraps() && self.x + expanded.len() as i32 > self.bounds.width {
+            self.wrap_line();
+        } else {
+            self.push_text(&expanded, fragments);
+        }
+    }
+
+    /// Add text at the current position, extending the previous fragment if
+    /// it ends right here.
+    fn push_text(&mut self, text: &str, fragments: &mut Vec<TextFragment>) {
+        let width = text.chars().count() as i32;
+        let x = self.bounds.x + self.x;
+        let y = self.bounds.y + self.line as i32;
+        match fragments.last_mut() {
+            Some(last) if last.rect.y == y && last.rect.x + last.rect.width == x => {
+                last.text.push_str(text);
+                last.rect.width += width;
+            }
+            _ => fragments.push(TextFragment {
+                rect: Rect {
+                    x,
+                    y,
+                    width,
+                    height: 1,
+                },
+                text: text.to_string(),
+            }),
+        }
+        self.x += width;
+        self.pending_space = false;
+        self.lines = self.lines.max(self.line + 1);
+    }
+}
+
+/// How far content on a line moves when it is aligned: the content at or
+/// after each column moves right by the distance given with it.
+#[derive(Debug, Default)]
+struct LineShifts(Vec<(i32, i32)>);
+
+impl LineShifts {
+    /// Move the whole line by `distance`.
+    fn by(distance: i32) -> Self {
+        LineShifts(vec![(i32::MIN, distance)])
+    }
+
+    /// Spread `extra` columns between the words of a line, whose characters
+    /// and their columns are given in order. The gaps before the first word
+    /// and after the last don't widen.
+    fn justify(text: &[(i32, char)], extra: i32) -> Self {
+        let first_word = text.iter().position(|&(_, c)| c != ' ');
+        let last_word = text.iter().rposition(|&(_, c)| c != ' ');
+        let (first, last) = match (first_word, last_word) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return LineShifts::default(),
+        };
+        // The column after each run of spaces between words.
+        let gaps = (first + 1..last)
+            .filter(|&i| text[i].1 != ' ' && text[i - 1].1 == ' ')
+            .map(|i| text[i - 1].0 + 1)
+            .collect::<Vec<_>>();
+        if gaps.is_empty() {
+            return LineShifts::default();
+        }
+        let count = gaps.len() as i32;
+        let mut moved = 0;
+        LineShifts(
+            gaps.into_iter()
+                .enumerate()
+                .map(|(i, column)| {
+                    // Earlier gaps take the columns that don't divide evenly.
+                    moved += extra / count + i32::from((i as i32) < extra % count);
+                    (column, moved)
+                })
+                .collect(),
+        )
+    }
+
+    /// How far content at `column` moves.
+    fn at(&self, column: i32) -> i32 {
+        self.0
+            .iter()
+            .take_while(|&&(start, _)| start <= column)
+            .last()
+            .map_or(0, |&(_, distance)| distance)
+    }
+
+    /// Move a fragment, widening the gaps inside it.
+    fn apply(&self, fragment: &mut TextFragment) {
+        let x = fragment.rect.x;
+        let end = x + fragment.rect.width;
+        let mut text = String::new();
+        for (column, c) in (x..).zip(fragment.text.chars()) {
+            text.push(c);
+            // Gaps at the end of the fragment move the next one instead.
+            if column + 1 < end {
+                let widened = self.at(column + 1) - self.at(column);
+                text.push_str(&" ".repeat(widened as usize));
+            }
+        }
+        fragment.rect.x += self.at(x);
+        fragment.rect.width = text.chars().count() as i32;
+        fragment.text = text;
+    }
+}
+
+/// The smallest rectangle containing all of the given fragments.
+fn fragments_bounds(fragments: &[TextFragment]) -> Rect {
+    let mut rects = fragments.iter().map(|f| f.rect);
+    let first = match rects.next() {
+        Some(first) => first,
+        None => return Rect::default(),
+    };
+    rects.fold(first, |a, b| {
+        let x = a.x.min(b.x);
+        let y = a.y.min(b.y);
+        Rect {
+            x,
+            y,
+            width: (a.x + a.width).max(b.x + b.width) - x,
+            height: (a.y + a.height).max(b.y + b.height) - y,
+        }
+    })
 }
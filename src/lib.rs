@@ -1,21 +1,29 @@
 pub mod content_tree;
 pub mod display;
 pub mod layout;
+pub mod markdown;
+pub mod render;
+pub mod span;
 pub mod style;
 pub mod style_tree;
 
-pub fn print_boxes(b: &layout::LayoutBox) {
-    fn print_boxes2(b: &layout::LayoutBox, i: usize) {
+pub use render::{render, Backend, RenderOptions, Renderer};
+pub use style::ColorMode;
+
+pub fn print_boxes(tree: &layout::LayoutTree) {
+    fn print_boxes2(tree: &layout::LayoutTree, b: layout::BoxId, i: usize) {
         for _ in 0..i {
             print!(" ");
         }
-        print!("{:?}", b.dimensions.border_box);
-        print!("\n");
-        for child in &b.children {
-            print_boxes2(child, i + 1);
+        print!("{:?}", tree[b].dimensions.border_box);
+        println!();
+        for &child in &tree[b].children {
+            print_boxes2(tree, child, i + 1);
         }
     }
-    print_boxes2(b, 0)
+    if let Some(root) = tree.root() {
+        print_boxes2(tree, root, 0)
+    }
 }
 
 #[cfg(test)]
@@ -23,6 +31,7 @@ mod tests {
     use super::content_tree::*;
     use super::display::*;
     use super::layout::*;
+    use super::span::*;
     use super::style::*;
     use super::style_tree::*;
 
@@ -103,146 +112,189 @@ mod tests {
                     selectors: vec![Selector {
                         id: Some("root".to_string()),
                         classes: vec![],
+                        pseudo_classes: vec![],
+                        pseudo_element: None,
                     }],
-                    declarations: vec![
-                        Declaration {
-                            name: "padding".to_string(),
-                            value: Value::AbsoluteLength(2),
-                        },
-                        Declaration {
-                            name: "margin".to_string(),
-                            value: Value::AbsoluteLength(3),
-                        },
-                        Declaration {
-                            name: "border".to_string(),
-                            value: Value::Border(Border::Double),
-                        },
-                    ],
+                    declarations: [
+                        sides("padding", Value::AbsoluteLength(2)),
+                        sides("margin", Value::AbsoluteLength(3)),
+                        sides("border", Value::Border(Border::Double)),
+                    ]
+                    .concat(),
+                    media: None,
+                    span: Span::default(),
                 },
                 Rule {
                     selectors: vec![Selector {
                         id: None,
                         classes: vec!["block".to_string()],
+                        pseudo_classes: vec![],
+                        pseudo_element: None,
                     }],
                     declarations: vec![Declaration {
                         name: "display".to_string(),
                         value: Value::Display(DisplayKind::Block),
+                        important: false,
+                        span: Span::default(),
                     }],
+                    media: None,
+                    span: Span::default(),
                 },
                 Rule {
                     selectors: vec![Selector {
                         id: None,
                         classes: vec!["a".to_string()],
+                        pseudo_classes: vec![],
+                        pseudo_element: None,
                     }],
-                    declarations: vec![
-                        Declaration {
-                            name: "padding".to_string(),
-                            value: Value::AbsoluteLength(2),
-                        },
-                        Declaration {
-                            name: "border".to_string(),
-                            value: Value::Border(Border::Light),
-                        },
-                        Declaration {
+                    declarations: [
+                        sides("padding", Value::AbsoluteLength(2)),
+                        sides("border", Value::Border(Border::Light)),
+                        vec![Declaration {
                             name: "height".to_string(),
                             value: Value::AbsoluteLength(12),
-                        },
-                    ],
+                            important: false,
+                            span: Span::default(),
+                        }],
+                    ]
+                    .concat(),
+                    media: None,
+                    span: Span::default(),
                 },
                 Rule {
                     selectors: vec![Selector {
                         id: None,
                         classes: vec!["b".to_string()],
+                        pseudo_classes: vec![],
+                        pseudo_element: None,
                     }],
-                    declarations: vec![
-                        Declaration {
-                            name: "width".to_string(),
-                            //value: Value::RelativeLength(0.5),
-                            value: Value::AbsoluteLength(20),
-                        },
-                        Declaration {
-                            name: "height".to_string(),
-                            value: Value::AbsoluteLength(2),
-                        },
-                        Declaration {
-                            name: "margin".to_string(),
-                            value: Value::Auto,
-                        },
-                    ],
+                    declarations: [
+                        vec![
+                            Declaration {
+                                name: "width".to_string(),
+                                //value: Value::RelativeLength(0.5),
+                                value: Value::AbsoluteLength(20),
+                                important: false,
+                                span: Span::default(),
+                            },
+                            Declaration {
+                                name: "height".to_string(),
+                                value: Value::AbsoluteLength(2),
+                                important: false,
+                                span: Span::default(),
+                            },
+                        ],
+                        sides("margin", Value::Auto),
+                    ]
+                    .concat(),
+                    media: None,
+                    span: Span::default(),
                 },
                 Rule {
                     selectors: vec![Selector {
                         id: None,
                         classes: vec!["c".to_string()],
+                        pseudo_classes: vec![],
+                        pseudo_element: None,
                     }],
                     declarations: vec![
                         Declaration {
                             name: "width".to_string(),
                             //value: Value::RelativeLength(0.5),
                             value: Value::AbsoluteLength(40),
+                            important: false,
+                            span: Span::default(),
                         },
                         Declaration {
                             name: "height".to_string(),
                             value: Value::AbsoluteLength(4),
+                            important: false,
+                            span: Span::default(),
                         },
                         Declaration {
                             name: "margin-left".to_string(),
                             value: Value::AbsoluteLength(6),
+                            important: false,
+                            span: Span::default(),
                         },
                         Declaration {
                             name: "margin-bottom".to_string(),
                             value: Value::AbsoluteLength(2),
+                            important: false,
+                            span: Span::default(),
                         },
                         Declaration {
                             name: "border-left".to_string(),
                             value: Value::Border(Border::Double),
+                            important: false,
+                            span: Span::default(),
                         },
                         Declaration {
                             name: "border-right".to_string(),
                             value: Value::Border(Border::Double),
+                            important: false,
+                            span: Span::default(),
                         },
                         Declaration {
                             name: "border-top".to_string(),
                             value: Value::Border(Border::Light),
+                            important: false,
+                            span: Span::default(),
                         },
                         Declaration {
                             name: "border-bottom".to_string(),
                             value: Value::Border(Border::Heavy),
+                            important: false,
+                            span: Span::default(),
                         },
                     ],
+                    media: None,
+                    span: Span::default(),
                 },
                 Rule {
                     selectors: vec![Selector {
                         id: None,
                         classes: vec!["d".to_string()],
+                        pseudo_classes: vec![],
+                        pseudo_element: None,
                     }],
                     declarations: vec![Declaration {
                         name: "padding-left".to_string(),
                         value: Value::AbsoluteLength(2),
+                        important: false,
+                        span: Span::default(),
                     }],
+                    media: None,
+                    span: Span::default(),
                 },
             ],
+            imports: Vec::new(),
         };
 
-        let styled_root = style_tree(&root, &stylesheet);
+        let document = Document::new(root);
+        let styles = style_tree(&document, &stylesheet);
 
-        //println!("{:#?}", styled_root);
+        //println!("{:#?}", styles);
 
-        let mut layout_root = build_layout_tree(&styled_root);
+        let mut layout_root = build_layout_tree(&document, &styles);
 
         //println!("{:#?}", layout_root);
 
-        layout_root.layout(&Dimensions {
-            border_box: Rect {
-                x: 0,
-                y: 0,
-                width: 80,
-                height: 0,
+        layout_root.layout(
+            &document,
+            &styles,
+            &Dimensions {
+                border_box: Rect {
+                    x: 0,
+                    y: 0,
+                    width: 80,
+                    height: 0,
+                },
+                margin: Default::default(),
+                padding: Default::default(),
+                border: Default::default(),
             },
-            margin: Default::default(),
-            padding: Default::default(),
-            border: Default::default(),
-        });
+        );
 
         //println!("{:#?}", layout_root);
 
@@ -254,7 +306,339 @@ mod tests {
         c.paint(&build_display_list(&layout_root));
 
         c.print();
+    }
+
+    fn text_lines(tree: &LayoutTree) -> Vec<(i32, i32, String)> {
+        let mut lines = Vec::new();
+        let mut stack = tree.root().into_iter().collect::<Vec<_>>();
+        while let Some(b) = stack.pop() {
+            for f in &tree[b].fragments {
+                lines.push((f.rect.x, f.rect.y, f.text.clone()));
+            }
+            stack.extend(tree[b].children.iter().rev());
+        }
+        lines
+    }
+
+    #[test]
+    fn white_space_modes() {
+        trace_init();
+
+        let html = "<div class=\"block\">\n  lots   of\n  space\n  <pre>  kept\n    as is</pre>\n  <p>  two\n lines  </p></div>";
+        let tss = ".block { display: block; }\n.pre { display: block; margin-bottom: 0; }\n.p { display: block; white-space: pre-line; }";
+        let document = html.parse::<Document>().unwrap();
+        let stylesheet = tss.parse::<Stylesheet>().unwrap();
+        let styles = style_tree(&document, &stylesheet);
+        let mut layout_root = build_layout_tree(&document, &styles);
+        layout_root.layout(&document, &styles, &Dimensions::from_width(12));
+
+        let lines = text_lines(&layout_root);
+        assert_eq!(
+            lines,
+            vec![
+                (0, 0, "lots of".to_string()),
+                (0, 1, "space".to_string()),
+                (0, 2, "  kept".to_string()),
+                (0, 3, "    as is".to_string()),
+                (0, 4, "two".to_string()),
+                (0, 5, "lines".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_text_stays_out_of_layout() {
+        trace_init();
+
+        let html =
+            "<div class=\"block\"><style>.x { }</style>a<script>if (a < b) {}</script> b</div>";
+        let document = html.parse::<Document>().unwrap();
+        let stylesheet = ".block { display: block; }".parse::<Stylesheet>().unwrap();
+        let styles = style_tree(&document, &stylesheet);
+        let mut layout_root = build_layout_tree(&document, &styles);
+        layout_root.layout(&document, &styles, &Dimensions::from_width(12));
+
+        let lines = text_lines(&layout_root);
+        assert_eq!(
+            lines,
+            vec![(0, 0, "a".to_string()), (1, 0, " b".to_string())]
+        );
+    }
+
+    #[test]
+    fn trees_are_owned() {
+        fn assert_owned<T: Send + Sync + 'static>() {}
+        assert_owned::<Document>();
+        assert_owned::<StyleTree>();
+        assert_owned::<LayoutTree>();
+    }
+
+    #[test]
+    fn anonymous_boxes_walk_up_for_style() {
+        let document = "<div class=\"block\">a<div class=\"block\">b</div>c</div>"
+            .parse::<Document>()
+            .unwrap();
+        let stylesheet = ".block { display: block; padding: 1; }"
+            .parse::<Stylesheet>()
+            .unwrap();
+        let styles = style_tree(&document, &stylesheet);
+        let mut tree = build_layout_tree(&document, &styles);
+        tree.layout(&document, &styles, &Dimensions::from_width(10));
+
+        let root = &tree[tree.root().unwrap()];
+        assert_eq!(root.node(), Some(document.root()));
+        let anonymous = root.children()[0];
+        assert_eq!(tree[anonymous].box_type(), BoxType::Anonymous);
+        assert_eq!(tree[anonymous].parent(), tree.root());
+        assert_eq!(
+            text_lines(&tree),
+            vec![
+                (1, 1, "a".to_string()),
+                (2, 3, "b".to_string()),
+                (1, 5, "c".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn incremental_relayout() {
+        trace_init();
+
+        let mut document = "<div class=\"block\"><p>one</p><p>two</p><p>three</p></div>"
+            .parse::<Document>()
+            .unwrap();
+        let stylesheet =
+            ".block { display: block; }\n.p { display: block; margin-bottom: 0; }\n.tall { height: 3; }\n.hidden { display: none; }"
+                .parse::<Stylesheet>()
+                .unwrap();
+        let root = document.root();
+        let ps = document[root].children().to_vec();
+        let width = Dimensions::from_width(10);
+
+        let mut styles = style_tree(&document, &stylesheet);
+        let mut tree = build_layout_tree(&document, &styles);
+        // The root, and a block, an anonymous box and a text box per `p`.
+        assert_eq!(tree.layout(&document, &styles, &width), 10);
+        assert_eq!(tree.layout(&document, &styles, &width), 0);
+
+        // Only the changed text and the boxes containing it are laid out;
+        // the other paragraphs keep their positions.
+        let text = document[ps[1]].children()[0];
+        document.set_text(text, "deux");
+        let restyled = styles.restyle(&document);
+        assert_eq!(restyled, vec![text]);
+        tree.restyle(&document, &styles, &restyled);
+        assert_eq!(tree.layout(&document, &styles, &width), 4);
+        assert_eq!(text_lines(&tree)[1], (0, 1, "deux".to_string()));
+
+        // Making the first paragraph taller moves the ones after it, so they
+        // are laid out again too.
+        document.add_class(ps[0], "tall");
+        let restyled = styles.restyle(&document);
+        tree.restyle(&document, &styles, &restyled);
+        assert_eq!(tree.layout(&document, &styles, &width), 10);
+        assert_eq!(
+            text_lines(&tree),
+            vec![
+                (0, 0, "one".to_string()),
+                (0, 3, "deux".to_string()),
+                (0, 4, "three".to_string()),
+            ]
+        );
+
+        // Changing `display` rebuilds the boxes.
+        document.add_class(ps[2], "hidden");
+        let restyled = styles.restyle(&document);
+        tree.restyle(&document, &styles, &restyled);
+        assert_eq!(tree.node_box(ps[2]), None);
+        assert_eq!(tree.layout(&document, &styles, &width), 7);
+
+        // Hiding the root leaves nothing to lay out, and showing it again
+        // brings the boxes back.
+        document.add_class(root, "hidden");
+        let restyled = styles.restyle(&document);
+        tree.restyle(&document, &styles, &restyled);
+        assert_eq!(tree.root(), None);
+        assert_eq!(tree.layout(&document, &styles, &width), 0);
+        document.remove_class(root, "hidden");
+        let restyled = styles.restyle(&document);
+        tree.restyle(&document, &styles, &restyled);
+        assert_eq!(tree.layout(&document, &styles, &width), 7);
+    }
+
+    #[test]
+    fn relayout_from_diff() {
+        trace_init();
+
+        let renders = [
+            "<div class=\"block\"><p>one</p><p>two</p><p>three</p></div>",
+            "<div class=\"block\"><p>one</p><p>deux</p><p>three</p></div>",
+            "<div class=\"block\"><p>three</p><p>one</p><p>four</p></div>",
+        ]
+        .iter()
+        .map(|html| html.parse::<Node>().unwrap())
+        .collect::<Vec<_>>();
+        let stylesheet = ".block { display: block; }\n.p { display: block; margin-bottom: 0; }"
+            .parse::<Stylesheet>()
+            .unwrap();
+        let width = Dimensions::from_width(10);
+
+        let mut document = Document::new(renders[0].clone());
+        let mut styles = style_tree(&document, &stylesheet);
+        let mut tree = build_layout_tree(&document, &styles);
+        assert_eq!(tree.layout(&document, &styles, &width), 10);
+
+        // Patching the text of one paragraph lays out only its boxes.
+        document.apply(&diff(&renders[0], &renders[1])).unwrap();
+        assert_eq!(document.to_node(document.root()), renders[1]);
+        let restyled = styles.restyle(&document);
+        tree.restyle(&document, &styles, &restyled);
+        assert_eq!(tree.layout(&document, &styles, &width), 4);
+        assert_eq!(text_lines(&tree)[1], (0, 1, "deux".to_string()));
+
+        // Moving, removing and inserting paragraphs rebuilds the boxes.
+        document.apply(&diff(&renders[1], &renders[2])).unwrap();
+        assert_eq!(document.to_node(document.root()), renders[2]);
+        let restyled = styles.restyle(&document);
+        tree.restyle(&document, &styles, &restyled);
+        assert_eq!(tree.layout(&document, &styles, &width), 10);
+        assert_eq!(
+            text_lines(&tree),
+            vec![
+                (0, 0, "three".to_string()),
+                (0, 1, "one".to_string()),
+                (0, 2, "four".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn aligned_block_content() {
+        let mut document = "<div><p>one</p><p>two</p></div>"
+            .parse::<Document>()
+            .unwrap();
+        let stylesheet = ".div { display: block; height: 6; align-content: end; }\n\
+                          .p { display: block; margin-bottom: 0; }"
+            .parse::<Stylesheet>()
+            .unwrap();
+        let width = Dimensions::from_width(10);
+        let mut styles = style_tree(&document, &stylesheet);
+        let mut tree = build_layout_tree(&document, &styles);
+        tree.layout(&document, &styles, &width);
+        assert_eq!(
+            text_lines(&tree),
+            vec![(0, 4, "one".to_string()), (0, 5, "two".to_string())]
+        );
+
+        // Laying out the container again doesn't move the unchanged
+        // paragraph a second time.
+        let p = document[document.root()].children()[1];
+        let text = document[p].children()[0];
+        document.set_text(text, "deux");
+        let restyled = styles.restyle(&document);
+        tree.restyle(&document, &styles, &restyled);
+        tree.layout(&document, &styles, &width);
+        assert_eq!(
+            text_lines(&tree),
+            vec![(0, 4, "one".to_string()), (0, 5, "deux".to_string())]
+        );
+    }
+
+    #[test]
+    fn aligned_inline_boxes() {
+        let document = "<p>x <b>yy</b> z</p>".parse::<Document>().unwrap();
+        let stylesheet = ".p { display: block; text-align: right; }\n.b { padding-left: 1; }"
+            .parse::<Stylesheet>()
+            .unwrap();
+        let styles = style_tree(&document, &stylesheet);
+        let mut tree = build_layout_tree(&document, &styles);
+        tree.layout(&document, &styles, &Dimensions::from_width(10));
+
+        let b = document[document.root()].children()[1];
+        let b = tree[tree.node_box(b).unwrap()].dimensions().border_box;
+        // The space before `yy` is placed after the padding, inside the box.
+        assert_eq!((b.x, b.width), (4, 4));
+        assert_eq!(
+            text_lines(&tree),
+            vec![
+                (3, 0, "x".to_string()),
+                (5, 0, " yy".to_string()),
+                (8, 0, " z".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn generated_content() {
+        let mut document = "<div class=\"block\"><p title=\"disk\">full</p></div>"
+            .parse::<Document>()
+            .unwrap();
+        let stylesheet = ".block { display: block; }\n\
+                          .p::before { content: \"ERROR(\" attr(title) \"): \"; }\n\
+                          .p::after { content: \"--\"; display: block; }"
+            .parse::<Stylesheet>()
+            .unwrap();
+        let p = document[document.root()].children()[0];
+        let width = Dimensions::from_width(20);
+
+        let mut styles = style_tree(&document, &stylesheet);
+        let mut tree = build_layout_tree(&document, &styles);
+        tree.layout(&document, &styles, &width);
+        // The `::before` box is inline, before the text, and the `::after`
+        // box is a block of its own.
+        let before = tree.pseudo_box(p, PseudoElement::Before).unwrap();
+        let after = tree.pseudo_box(p, PseudoElement::After).unwrap();
+        assert_eq!(
+            tree[before].box_type(),
+            BoxType::InlinePseudo(p, PseudoElement::Before)
+        );
+        assert_eq!(
+            tree[tree[before].parent().unwrap()].box_type(),
+            BoxType::Anonymous
+        );
+        assert_eq!(tree[after].parent(), tree.node_box(p));
+        assert_eq!(
+            text_lines(&tree),
+            vec![
+                (0, 0, "ERROR(disk):".to_string()),
+                (12, 0, " full".to_string()),
+                (0, 1, "--".to_string()),
+            ]
+        );
+
+        // Changing an attribute generates the content again.
+        document.set_attribute(p, "title", Some("cpu".to_string()));
+        let restyled = styles.restyle(&document);
+        tree.restyle(&document, &styles, &restyled);
+        tree.layout(&document, &styles, &width);
+        assert_eq!(text_lines(&tree)[0], (0, 0, "ERROR(cpu):".to_string()));
+    }
+
+    #[test]
+    fn resizing_restyles_media_rules() {
+        trace_init();
+
+        let document = "<div class=\"block\"><p>one</p><p>two</p></div>"
+            .parse::<Document>()
+            .unwrap();
+        let stylesheet = ".block { display: block; }\n\
+                          .p { margin-bottom: 0; }\n\
+                          @media (max-width: 20) { .p { display: inline; } }"
+            .parse::<Stylesheet>()
+            .unwrap();
+        let cascade = Cascade::from(&stylesheet).with_viewport(Viewport::new(40));
+        let mut styles = style_tree(&document, cascade);
+        let mut tree = build_layout_tree(&document, &styles);
+        tree.layout(&document, &styles, &Dimensions::from_width(40));
+        assert_eq!(text_lines(&tree)[1], (0, 1, "two".to_string()));
+
+        // Nothing changes until a query changes whether it matches.
+        assert_eq!(styles.resize(&document, Viewport::new(30)), vec![]);
 
-        panic!();
+        let restyled = styles.resize(&document, Viewport::new(20));
+        assert_eq!(restyled.len(), 5);
+        tree.restyle(&document, &styles, &restyled);
+        tree.layout(&document, &styles, &Dimensions::from_width(20));
+        assert_eq!(text_lines(&tree)[1], (3, 0, "two".to_string()));
     }
 }
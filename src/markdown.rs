@@ -0,0 +1,286 @@
+//! Building content trees from Markdown.
+//!
+//! Markdown is converted to the same elements its HTML rendering would use
+//! (`h1`, `p`, `em`, `ul`, `table` and so on), so it can be styled like any
+//! other markup.
+
+use crate::content_tree::Node;
+use pulldown_cmark::{Alignment, CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag};
+
+/// Convert a Markdown document to a content tree.
+///
+/// The result is a `<div class="markdown">` element containing the document.
+/// Raw HTML in the Markdown source is kept as text.
+pub fn to_content_tree(markdown: &str) -> Node {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_HEADING_ATTRIBUTES;
+    let mut builder = TreeBuilder::new();
+    for event in Parser::new_ext(markdown, options) {
+        builder.event(event);
+    }
+    builder.finish()
+}
+
+struct TreeBuilder {
+    /// The elements that are currently open, starting with the root.
+    open: Vec<Node>,
+    /// The alignment of each column of the current table.
+    alignments: Vec<Alignment>,
+    /// The index of the next cell in the current table row.
+    column: usize,
+}
+
+impl TreeBuilder {
+    fn new() -> Self {
+        let mut root = Node::element("div", Vec::new());
+        root.add_class("markdown");
+        TreeBuilder {
+            open: vec![root],
+            alignments: Vec::new(),
+            column: 0,
+        }
+    }
+
+    fn finish(mut self) -> Node {
+        while self.open.len() > 1 {
+            self.close();
+        }
+        self.open.pop().expect("the root is never closed")
+    }
+
+    fn current(&mut self) -> &mut Node {
+        self.open.last_mut().expect("the root is never closed")
+    }
+
+    fn open(&mut self, tag_name: &str) -> &mut Node {
+        self.open.push(Node::element(tag_name, Vec::new()));
+        self.current()
+    }
+
+    fn close(&mut self) {
+        let node = self.open.pop().expect("the root is never closed");
+        self.current().append_child(node);
+    }
+
+    /// Add text to the current element, merging it with any text just
+    /// before it.
+    fn text(&mut self, text: &str) {
+        let current = self.current();
+        let last = current.children_mut().last().filter(|n| n.text().is_some());
+        match last {
+            Some(last) => {
+                let merged = format!("{}{}", last.text().unwrap_or_default(), text);
+                last.set_text(merged);
+            }
+            None => current.append_child(Node::from(text)),
+        }
+    }
+
+    fn leaf(&mut self, tag_name: &str, text: &str) {
+        self.open(tag_name);
+        self.text(text);
+        self.close();
+    }
+
+    fn event(&mut self, event: Event<'_>) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) | Event::Html(text) => self.text(&text),
+            Event::Code(code) => self.leaf("code", &code),
+            Event::SoftBreak => self.text("\n"),
+            Event::HardBreak => {
+                self.open("br");
+                self.close();
+            }
+            Event::Rule => {
+                self.open("hr");
+                self.close();
+            }
+            Event::TaskListMarker(checked) => {
+                self.open("span").add_class("task-marker");
+                self.text(if checked { "[x]" } else { "[ ]" });
+                self.close();
+                self.text(" ");
+            }
+            Event::FootnoteReference(label) => {
+                self.open("sup").add_class("footnote-reference");
+                self.text(&format!("[{}]", label));
+                self.close();
+            }
+        }
+    }
+
+    fn start(&mut self, tag: Tag<'_>) {
+        match tag {
+            Tag::Paragraph => {
+                self.open("p");
+            }
+            Tag::Heading(level, id, classes) => {
+                let heading = self.open(heading_tag(level));
+                heading.set_id(id.map(String::from));
+                for class in classes {
+                    heading.add_class(class);
+                }
+            }
+            Tag::BlockQuote => {
+                self.open("blockquote");
+            }
+            Tag::CodeBlock(kind) => {
+                self.open("pre");
+                let code = self.open("code");
+                if let CodeBlockKind::Fenced(info) = kind {
+                    if let Some(language) = info.split_whitespace().next() {
+                        code.add_class(&format!("language-{}", language));
+                    }
+                }
+            }
+            Tag::List(None) => {
+                self.open("ul");
+            }
+            Tag::List(Some(start)) => {
+                let list = self.open("ol");
+                if start != 1 {
+                    list.set_attribute("start", Some(start.to_string()));
+                }
+            }
+            Tag::Item => {
+                self.open("li");
+            }
+            Tag::FootnoteDefinition(label) => {
+                let definition = self.open("div");
+                definition.add_class("footnote-definition");
+                definition.set_id(Some(label.to_string()));
+            }
+            Tag::Table(alignments) => {
+                self.alignments = alignments;
+                self.open("table");
+            }
+            Tag::TableHead => {
+                self.open("thead");
+                self.open("tr");
+                self.column = 0;
+            }
+            Tag::TableRow => {
+                self.open("tr");
+                self.column = 0;
+            }
+            Tag::TableCell => {
+                let in_head = self.open.iter().any(|n| n.tag_name() == Some("thead"));
+                let alignment = self.alignments.get(self.column).copied();
+                self.column += 1;
+                let cell = self.open(if in_head { "th" } else { "td" });
+                match alignment {
+                    Some(Alignment::Left) => cell.add_class("align-left"),
+                    Some(Alignment::Center) => cell.add_class("align-center"),
+                    Some(Alignment::Right) => cell.add_class("align-right"),
+                    Some(Alignment::None) | None => {}
+                }
+            }
+            Tag::Emphasis => {
+                self.open("em");
+            }
+            Tag::Strong => {
+                self.open("strong");
+            }
+            Tag::Strikethrough => {
+                self.open("del");
+            }
+            Tag::Link(_, destination, title) => {
+                let link = self.open("a");
+                link.set_attribute("href", Some(destination.to_string()));
+                if !title.is_empty() {
+                    link.set_attribute("title", Some(title.to_string()));
+                }
+            }
+            Tag::Image(_, destination, title) => {
+                let image = self.open("img");
+                image.set_attribute("src", Some(destination.to_string()));
+                if !title.is_empty() {
+                    image.set_attribute("title", Some(title.to_string()));
+                }
+            }
+        }
+    }
+
+    fn end(&mut self, tag: Tag<'_>) {
+        match tag {
+            Tag::CodeBlock(_) => {
+                self.close();
+                self.close();
+            }
+            Tag::TableHead => {
+                self.close();
+                self.close();
+                self.open("tbody");
+            }
+            Tag::Table(_) => {
+                // Close the `tbody` opened after the table head.
+                self.close();
+                self.close();
+            }
+            _ => self.close(),
+        }
+    }
+}
+
+fn heading_tag(level: HeadingLevel) -> &'static str {
+    match level {
+        HeadingLevel::H1 => "h1",
+        HeadingLevel::H2 => "h2",
+        HeadingLevel::H3 => "h3",
+        HeadingLevel::H4 => "h4",
+        HeadingLevel::H5 => "h5",
+        HeadingLevel::H6 => "h6",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn markup(markdown: &str) -> String {
+        to_content_tree(markdown).to_string()
+    }
+
+    #[test]
+    fn blocks_and_inlines() {
+        assert_eq!(
+            markup("# Title {#top}\n\nSome *em*, **strong** and `code`\nwith [a link](http://x \"t\").\n"),
+            "<div class=\"markdown\"><h1 id=\"top\">Title</h1>\
+             <p>Some <em>em</em>, <strong>strong</strong> and <code>code</code>\n\
+             with <a href=\"http://x\" title=\"t\">a link</a>.</p></div>"
+        );
+    }
+
+    #[test]
+    fn lists_quotes_and_code() {
+        assert_eq!(
+            markup("> quoted\n\n3. three\n4. four\n\n- a\n  - b\n\n---\n\n```rust\nfn main() {}\n```\n"),
+            "<div class=\"markdown\"><blockquote><p>quoted</p></blockquote>\
+             <ol start=\"3\"><li>three</li><li>four</li></ol>\
+             <ul><li>a<ul><li>b</li></ul></li></ul><hr>\
+             <pre><code class=\"language-rust\">fn main() {}\n</code></pre></div>"
+        );
+    }
+
+    #[test]
+    fn tables() {
+        assert_eq!(
+            markup("| a | b |\n|:--|--:|\n| 1 | 2 |\n"),
+            "<div class=\"markdown\"><table>\
+             <thead><tr><th class=\"align-left\">a</th><th class=\"align-right\">b</th></tr></thead>\
+             <tbody><tr><td class=\"align-left\">1</td><td class=\"align-right\">2</td></tr></tbody>\
+             </table></div>"
+        );
+    }
+
+    #[test]
+    fn output_parses_as_markup() {
+        let tree = to_content_tree("A *b* & `<c>`\n\n| x |\n|---|\n| y |\n");
+        assert_eq!(tree.to_string().parse::<Node>(), Ok(tree));
+    }
+}
@@ -0,0 +1,434 @@
+//! Rendering documents in one call.
+//!
+//! [`render`] runs the whole pipeline: parsing, styling, layout and
+//! painting. A [`Renderer`] keeps a parsed stylesheet around for rendering
+//! many documents with it.
+
+use crate::content_tree::{Document, Node};
+use crate::display::{self, DebugCanvas, TerminalCanvas, TextCanvas};
+use crate::layout::{build_layout_tree, Dimensions};
+use crate::style::{Cascade, ColorMode, Loader, MemoryLoader, Origin, Stylesheet, Viewport};
+use crate::style_tree::style_tree;
+
+/// Where rendered output goes, which decides its format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// Plain text, one line per row, without trailing whitespace.
+    Text,
+    /// Escape sequences that draw the document at the top left of a
+    /// terminal screen.
+    Terminal,
+    /// Text with block boxes shaded, for seeing how a document was laid out.
+    Debug,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    /// The width to lay the document out in, in columns.
+    pub width: usize,
+    /// The height of the screen, in rows, which `@media` queries can test,
+    /// or `None` if the output can be as tall as it needs.
+    pub height: Option<usize>,
+    pub backend: Backend,
+    /// How the `color` and `border-color` of text and borders are written by
+    /// the text and terminal backends.
+    pub color: ColorMode,
+    /// The stylesheet applied beneath the author's, or `None` for no
+    /// defaults at all.
+    pub default_stylesheet: Option<Stylesheet>,
+    /// The user's stylesheet, which overrides the defaults, and overrides the
+    /// author's stylesheet where it is `!important`.
+    pub user_stylesheet: Option<Stylesheet>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            width: 80,
+            height: None,
+            backend: Backend::Text,
+            color: ColorMode::None,
+            default_stylesheet: Some(Stylesheet::user_agent()),
+            user_stylesheet: None,
+        }
+    }
+}
+
+/// Render markup styled with a stylesheet.
+///
+/// Styles in `<style>` elements apply on top of `tss`. Linked stylesheets
+/// and imports can't be loaded; use a [`Renderer`] with a loader for those.
+pub fn render(html: &str, tss: &str, options: RenderOptions) -> Result<String, String> {
+    Renderer::new(tss, options)?.render(html)
+}
+
+/// Renders documents with a stylesheet and options.
+pub struct Renderer {
+    stylesheet: Stylesheet,
+    options: RenderOptions,
+    loader: Box<dyn Loader>,
+}
+
+impl Renderer {
+    pub fn new(tss: &str, options: RenderOptions) -> Result<Self, String> {
+        Ok(Renderer {
+            stylesheet: tss.parse()?,
+            options,
+            loader: Box::new(MemoryLoader::new()),
+        })
+    }
+
+    /// Load `<link rel="stylesheet">` sheets and `@import`s with `loader`.
+    pub fn with_loader(mut self, loader: impl Loader + 'static) -> Self {
+        self.loader = Box::new(loader);
+        self
+    }
+
+    /// Parse and render markup, including the styles it embeds or links to.
+    pub fn render(&self, html: &str) -> Result<String, String> {
+        let root = html.parse::<Node>()?;
+        let stylesheet = Stylesheet::for_document(
+            &root,
+            std::slice::from_ref(&self.stylesheet),
+            self.loader.as_ref(),
+        )?;
+        self.render_with(&Document::new(root), &stylesheet)
+    }
+
+    /// Render a document with the renderer's stylesheet alone.
+    pub fn render_document(&self, document: &Document) -> Result<String, String> {
+        let stylesheet = self
+            .stylesheet
+            .clone()
+            .resolve_imports(self.loader.as_ref())?;
+        self.render_with(document, &stylesheet)
+    }
+
+    fn render_with(&self, document: &Document, stylesheet: &Stylesheet) -> Result<String, String> {
+        let mut viewport = Viewport::new(self.options.width as i32);
+        viewport.height = self.options.height.map(|h| h as i32);
+        let mut cascade = Cascade::new().with_viewport(viewport);
+        if let Some(ref defaults) = self.options.default_stylesheet {
+            cascade.push(Origin::UserAgent, defaults.clone());
+        }
+        if let Some(ref user) = self.options.user_stylesheet {
+            cascade.push(Origin::User, user.clone());
+        }
+        cascade.push(Origin::Author, stylesheet.clone());
+        let styles = style_tree(document, cascade);
+        let mut layout_tree = build_layout_tree(document, &styles);
+        let width = Dimensions::from_width(self.options.width as i32);
+        layout_tree.layout(document, &styles, &width);
+
+        let display_list = display::build_display_list(&layout_tree);
+        let (width, height) = display::layout_size(&layout_tree);
+        match self.options.backend {
+            Backend::Text => {
+                let mut canvas = TextCanvas::new(width, height).with_color_mode(self.options.color);
+                canvas.paint(&display_list);
+                Ok(canvas.to_string())
+            }
+            Backend::Terminal => {
+                let mut canvas =
+                    TerminalCanvas::new(width, height).with_color_mode(self.options.color);
+                canvas.paint(&display_list);
+                let mut out = Vec::new();
+                canvas.present(&mut out).map_err(|e| e.to_string())?;
+                String::from_utf8(out).map_err(|e| e.to_string())
+            }
+            Backend::Debug => {
+                let mut canvas = DebugCanvas::new(width, height);
+                canvas.paint(&display_list);
+                Ok(canvas.to_string())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn render_in_one_call() {
+        let html = "<div class=\"box\"><style>.box { border: light; }</style>\
+                    Hello, <b>world</b>!</div>";
+        let tss = ".box { display: block; width: 12; padding-left: 1; }";
+        let options = RenderOptions {
+            width: 20,
+            ..Default::default()
+        };
+        assert_eq!(
+            render(html, tss, options),
+            Ok("┌──────────┐\n│ Hello,   │\n│ world!   │\n└──────────┘\n".to_string())
+        );
+
+        let terminal = render(
+            "<p>hi</p>",
+            "",
+            RenderOptions {
+                backend: Backend::Terminal,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(terminal.contains("\x1b[1;1Hhi"), "{:?}", terminal);
+    }
+
+    #[test]
+    fn default_stylesheet() {
+        // Without defaults, `<style>` text is rendered like any other.
+        let html = "<div><style>.a { }</style>text</div>";
+        let with = RenderOptions::default();
+        let without = RenderOptions {
+            default_stylesheet: None,
+            ..Default::default()
+        };
+        assert_eq!(render(html, "", with), Ok("text\n".to_string()));
+        assert_eq!(render(html, "", without), Ok(".a { }text\n".to_string()));
+    }
+
+    #[test]
+    fn unstyled_markup() {
+        let html = "<div><h1>Title</h1><p>One</p><ul><li>a</li><li>b</li></ul>\
+                    <hr><blockquote>quoted</blockquote></div>";
+        let options = || RenderOptions {
+            width: 10,
+            ..Default::default()
+        };
+        assert_eq!(
+            render(html, "", options()),
+            Ok("Title\n══════════\n\nOne\n\n• a\n• b\n──────────\n│ quoted\n".to_string())
+        );
+        let without = RenderOptions {
+            default_stylesheet: None,
+            ..options()
+        };
+        assert_eq!(
+            render(html, "", without),
+            Ok("TitleOneab\nquoted\n".to_string())
+        );
+    }
+
+    #[test]
+    fn user_stylesheet() {
+        let tss = ".a { display: block; width: 3; border: light; }";
+        let options = |user: &str| RenderOptions {
+            user_stylesheet: Some(user.parse().unwrap()),
+            ..Default::default()
+        };
+        let html = "<p class=\"a\">x</p>";
+        assert_eq!(
+            render(html, tss, options(".a { border: heavy; }")),
+            Ok("┌─┐\n│x│\n└─┘\n".to_string())
+        );
+        assert_eq!(
+            render(html, tss, options(".a { border: heavy !important; }")),
+            Ok("┏━┓\n┃x┃\n┗━┛\n".to_string())
+        );
+    }
+
+    #[test]
+    fn custom_properties() {
+        let tss = ".theme { --frame: double; }\n\
+                   .box { display: block; width: 3; border: var(--frame, light); }";
+        assert_eq!(
+            render(
+                "<div><div class=\"theme\"><span class=\"box\">x</span></div><span class=\"box\">y</span></div>",
+                tss,
+                Default::default()
+            ),
+            Ok("╔═╗\n║x║\n╚═╝\n┌─┐\n│y│\n└─┘\n".to_string())
+        );
+    }
+
+    #[test]
+    fn media_queries() {
+        let tss = ".side { display: block; }\n\
+                   @media (max-width: 20) or (max-height: 5) { .side { display: none; } }";
+        let html = "<div><div class=\"side\">menu</div><div>body</div></div>";
+        let options = |width, height| RenderOptions {
+            width,
+            height,
+            ..Default::default()
+        };
+        assert_eq!(
+            render(html, tss, options(80, None)),
+            Ok("menu\nbody\n".to_string())
+        );
+        assert_eq!(
+            render(html, tss, options(20, None)),
+            Ok("body\n".to_string())
+        );
+        assert_eq!(
+            render(html, tss, options(80, Some(5))),
+            Ok("body\n".to_string())
+        );
+    }
+
+    #[test]
+    fn generated_content() {
+        let tss = ".p { margin-bottom: 0; }\n\
+                   .warn::before { content: \"▶ \"; }\n\
+                   .warn::after { content: ' (' attr(id) ')'; }\n\
+                   .quiet::before { content: none; }";
+        let html = "<div><p class=\"warn\" id=\"w1\">low disk</p><p class=\"quiet\">ok</p></div>";
+        assert_eq!(
+            render(html, tss, Default::default()),
+            Ok("▶ low disk (w1)\nok\n".to_string())
+        );
+    }
+
+    #[test]
+    fn large_counters() {
+        let tss = ".p { counter-increment: a 2147483647; margin-bottom: 0; }\n\
+                   .p::before { content: counter(a); }";
+        assert_eq!(
+            render("<div><p></p><p></p></div>", tss, Default::default()),
+            Ok("2147483647\n2147483647\n".to_string())
+        );
+    }
+
+    #[test]
+    fn lists() {
+        let html = "<div><ol><li>one<ol><li>a</li><li>b</li></ol></li><li>two\
+                    <ul><li>x</li></ul></li></ol></div>";
+        assert_eq!(
+            render(html, "", Default::default()),
+            Ok("1. one\n   1. a\n   2. b\n2. two\n   • x\n".to_string())
+        );
+
+        // Counters can number nested items with their ancestors' numbers.
+        let tss = ".ol { padding-left: 4; }\n\
+                   .li { list-style-type: none; }\n\
+                   .li::before { content: counters(list-item, \".\") \" \"; }";
+        assert_eq!(
+            render(html, tss, Default::default()),
+            Ok("    1 one\n        1.1 a\n        1.2 b\n    2 two\n      2.1 x\n".to_string())
+        );
+
+        // Both list styles are inherited, so the `ul` inside is inside too.
+        let tss = ".ol { list-style-type: lower-roman; list-style-position: inside; }\n\
+                   .ul { list-style-type: \"- \"; }";
+        assert_eq!(
+            render(html, tss, Default::default()),
+            Ok("   i. one\n      i. a\n      ii. b\n   ii. two\n     - x\n".to_string())
+        );
+    }
+
+    #[test]
+    fn text_alignment() {
+        let tss = ".p { margin-bottom: 0; }\n\
+                   .r { text-align: right; }\n\
+                   .c { text-align: center; }\n\
+                   .j { text-align: justify; }";
+        let html = "<div><p class=\"r\">ab</p><p class=\"c\">ab c</p>\
+                    <p class=\"j\">aa <b>bb</b> cc dd ee ff gg</p></div>";
+        let options = RenderOptions {
+            width: 10,
+            ..Default::default()
+        };
+        assert_eq!(
+            render(html, tss, options),
+            Ok("        ab\n   ab c\naa  bb  cc\ndd  ee  ff\ngg\n".to_string())
+        );
+    }
+
+    #[test]
+    fn content_alignment() {
+        let tss = ".p { margin-bottom: 0; }\n\
+                   .div { height: 5; border: light; }\n\
+                   .c { align-content: center; }\n\
+                   .e { align-content: end; }";
+        let options = RenderOptions {
+            width: 5,
+            ..Default::default()
+        };
+        let render_in = |class: &str| {
+            let html = format!("<div class=\"{}\"><p>a</p></div>", class);
+            render(&html, tss, options.clone())
+        };
+        assert_eq!(
+            render_in("s"),
+            Ok("┌───┐\n│a  │\n│   │\n│   │\n└───┘\n".to_string())
+        );
+        assert_eq!(
+            render_in("c"),
+            Ok("┌───┐\n│   │\n│a  │\n│   │\n└───┘\n".to_string())
+        );
+        assert_eq!(
+            render_in("e"),
+            Ok("┌───┐\n│   │\n│   │\n│a  │\n└───┘\n".to_string())
+        );
+    }
+
+    #[test]
+    fn colors() {
+        let html = "<div><p>a <b>b</b></p><p class=\"box\">c</p></div>";
+        let tss = ".div { display: block; }\n\
+                   .p { display: block; margin-bottom: 0; color: red; }\n\
+                   .b { color: #00f; }\n\
+                   .box { border: light; border-left-color: green; width: 3; }";
+        let render_in = |color| {
+            let options = RenderOptions {
+                color,
+                ..Default::default()
+            };
+            render(html, tss, options)
+        };
+        assert_eq!(
+            render_in(ColorMode::None),
+            Ok("a b\n┌─┐\n│c│\n└─┘\n".to_string())
+        );
+        // Text inherits its color, and borders default to it.
+        assert_eq!(
+            render_in(ColorMode::Basic),
+            Ok("\x1b[31ma\x1b[34m b\x1b[0m\n\
+                \x1b[31m┌─┐\x1b[0m\n\
+                \x1b[32m│\x1b[31mc│\x1b[0m\n\
+                \x1b[31m└─┘\x1b[0m\n"
+                .to_string())
+        );
+        assert_eq!(
+            render_in(ColorMode::TrueColor).map(|out| out.lines().next().map(String::from)),
+            Ok(Some(
+                "\x1b[38;2;255;0;0ma\x1b[38;2;0;0;255m b\x1b[0m".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn hidden_root() {
+        // The default stylesheet hides `script`.
+        assert_eq!(
+            render("<script>x</script>", "", Default::default()),
+            Ok(String::new())
+        );
+        assert_eq!(
+            render(
+                "<div>a</div>",
+                ".div { display: none; }",
+                Default::default()
+            ),
+            Ok(String::new())
+        );
+    }
+
+    #[test]
+    fn linked_stylesheets() {
+        let renderer = Renderer::new("", RenderOptions::default())
+            .unwrap()
+            .with_loader(MemoryLoader::new().with("a.tss", ".b { display: none; }"));
+        assert_eq!(
+            renderer.render("<div><link rel=\"stylesheet\" href=\"a.tss\">a<b>b</b></div>"),
+            Ok("a\n".to_string())
+        );
+        assert!(render(
+            "<link rel=\"stylesheet\" href=\"a.tss\">",
+            "",
+            Default::default()
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,112 @@
+use std::ops::Range;
+
+/// A position in source text.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Position {
+    /// The byte offset from the start of the source.
+    pub offset: usize,
+    /// The line number, starting at 1.
+    pub line: usize,
+    /// The column number in characters, starting at 1.
+    pub column: usize,
+}
+
+/// The region of source text that a parsed item came from.
+///
+/// Items that were constructed by hand rather than parsed have an empty
+/// default span.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    /// The byte range of the spanned text.
+    pub fn range(&self) -> Range<usize> {
+        self.start.offset..self.end.offset
+    }
+
+    /// Record the span of the input consumed by a parser, given its input
+    /// and the input it left remaining.
+    ///
+    /// `nom` parsers only see a suffix of the source, so until the span is
+    /// passed to [`Span::resolve`], offsets are counted from the *end* of the
+    /// source.
+    pub(crate) fn consumed(input: &str, remaining: &str) -> Span {
+        Span {
+            start: Position {
+                offset: input.len(),
+                ..Position::default()
+            },
+            end: Position {
+                offset: remaining.len(),
+                ..Position::default()
+            },
+        }
+    }
+
+    /// Turn a span recorded by [`Span::consumed`] into absolute offsets and
+    /// line and column numbers within `source`.
+    pub(crate) fn resolve(&mut self, source: &LineIndex) {
+        self.start = source.position(source.len - self.start.offset);
+        self.end = source.position(source.len - self.end.offset);
+    }
+}
+
+/// The start offsets of each line in a source text, used to resolve spans.
+pub(crate) struct LineIndex<'a> {
+    source: &'a str,
+    len: usize,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub(crate) fn new(source: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        LineIndex {
+            source,
+            len: source.len(),
+            line_starts,
+        }
+    }
+
+    fn position(&self, offset: usize) -> Position {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let line_start = self.line_starts[line];
+        Position {
+            offset,
+            line: line + 1,
+            column: self.source[line_start..offset].chars().count() + 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_positions() {
+        let source = "ab\nc🥰d\n";
+        let index = LineIndex::new(source);
+        // "d" is preceded by "ab\nc🥰".
+        let mut span = Span::consumed(&source[8..], &source[9..]);
+        span.resolve(&index);
+        assert_eq!(span.range(), 8..9);
+        assert_eq!(
+            span.start,
+            Position {
+                offset: 8,
+                line: 2,
+                column: 3
+            }
+        );
+        assert_eq!(span.end.column, 4);
+    }
+}
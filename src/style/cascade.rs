@@ -0,0 +1,194 @@
+use super::*;
+
+/// Where a stylesheet came from, which decides how its declarations rank
+/// against those of other stylesheets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Origin {
+    /// Built-in defaults, such as [`Stylesheet::user_agent`].
+    UserAgent,
+    /// The preferences of whoever is running the program.
+    User,
+    /// The program's own styles.
+    Author,
+}
+
+/// Stylesheets from several origins, applied together.
+///
+/// Declarations are ranked by origin and importance, then by the specificity
+/// of their selector, then by the order they were added in. Normal
+/// declarations rank user agent, then user, then author, and `!important`
+/// ones rank above all normal declarations in the reverse order, so that
+/// users can insist on a style whatever the author's theme says.
+///
+/// Rules in `@media` blocks only apply if their query matches the cascade's
+/// viewport.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cascade {
+    sheets: Vec<(Origin, Stylesheet)>,
+    viewport: Viewport,
+}
+
+impl Cascade {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, origin: Origin, stylesheet: Stylesheet) -> Self {
+        self.push(origin, stylesheet);
+        self
+    }
+
+    /// Add a stylesheet, after the ones already added.
+    pub fn push(&mut self, origin: Origin, stylesheet: Stylesheet) {
+        self.sheets.push((origin, stylesheet));
+    }
+
+    pub fn with_viewport(mut self, viewport: Viewport) -> Self {
+        self.viewport = viewport;
+        self
+    }
+
+    pub fn viewport(&self) -> Viewport {
+        self.viewport
+    }
+
+    /// Change the viewport, returning whether any `@media` query changed
+    /// whether it matches.
+    pub fn set_viewport(&mut self, viewport: Viewport) -> bool {
+        let old = std::mem::replace(&mut self.viewport, viewport);
+        self.sheets
+            .iter()
+            .flat_map(|(_, sheet)| &sheet.rules)
+            .filter_map(|rule| rule.media.as_ref())
+            .any(|query| query.matches(&old) != query.matches(&viewport))
+    }
+
+    pub fn sheets(&self) -> impl Iterator<Item = (Origin, &Stylesheet)> + '_ {
+        self.sheets.iter().map(|(origin, sheet)| (*origin, sheet))
+    }
+
+    /// The declarations that apply to `subject`, from lowest to highest
+    /// precedence.
+    pub fn matching_declarations(&self, subject: &Subject) -> Vec<&Declaration> {
+        let mut declarations = Vec::new();
+        for (origin, sheet) in &self.sheets {
+            for (specificity, rule) in sheet.matching_rules(subject) {
+                if rule
+                    .media
+                    .as_ref()
+                    .is_some_and(|q| !q.matches(&self.viewport))
+                {
+                    continue;
+                }
+                for declaration in &rule.declarations {
+                    let rank = rank(*origin, declaration.important);
+                    declarations.push(((rank, specificity), declaration));
+                }
+            }
+        }
+        // The sort is stable, so declarations that tie keep their source
+        // order and later ones win.
+        declarations.sort_by_key(|&(key, _)| key);
+        declarations.into_iter().map(|(_, d)| d).collect()
+    }
+}
+
+/// A stylesheet on its own is the author's, applied over the user agent
+/// stylesheet.
+impl From<&Stylesheet> for Cascade {
+    fn from(stylesheet: &Stylesheet) -> Self {
+        Cascade::new()
+            .with(Origin::UserAgent, Stylesheet::user_agent())
+            .with(Origin::Author, stylesheet.clone())
+    }
+}
+
+impl From<&Cascade> for Cascade {
+    fn from(cascade: &Cascade) -> Self {
+        cascade.clone()
+    }
+}
+
+/// Where declarations from `origin` rank, lowest first.
+fn rank(origin: Origin, important: bool) -> u8 {
+    match (important, origin) {
+        (false, Origin::UserAgent) => 0,
+        (false, Origin::User) => 1,
+        (false, Origin::Author) => 2,
+        (true, Origin::Author) => 3,
+        (true, Origin::User) => 4,
+        (true, Origin::UserAgent) => 5,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn origins_and_importance() {
+        let sheet = |s: &str| s.parse::<Stylesheet>().unwrap();
+        let cascade = Cascade::new()
+            .with(
+                Origin::UserAgent,
+                sheet(".a { width: 1; height: 1 !important; }"),
+            )
+            .with(
+                Origin::User,
+                sheet(".a { width: 2; padding: 2 !important; }"),
+            )
+            .with(
+                Origin::Author,
+                sheet("#x { width: 3; padding: 3; height: 3 !important; }\n.a { width: 4; }"),
+            )
+            .with(Origin::Author, sheet(".a { width: 5; }"));
+
+        let node = "<p id=\"x\" class=\"a\">x</p>".parse::<Node>().unwrap();
+        let subject = Subject::at(&node.cursor()).unwrap();
+        let winner = |name| {
+            cascade
+                .matching_declarations(&subject)
+                .into_iter()
+                .rfind(|d| d.name() == name)
+                .map(|d| d.value().clone())
+        };
+        // Author beats user beats user agent, and specificity beats order.
+        assert_eq!(winner("width"), Some(Value::AbsoluteLength(3)));
+        // Important declarations win in the reverse order of origins.
+        assert_eq!(winner("height"), Some(Value::AbsoluteLength(1)));
+        assert_eq!(winner("padding-top"), Some(Value::AbsoluteLength(2)));
+
+        // Between sheets of the same origin, the later one wins.
+        let node = "<p class=\"a\">x</p>".parse::<Node>().unwrap();
+        let subject = Subject::at(&node.cursor()).unwrap();
+        let widths = cascade
+            .matching_declarations(&subject)
+            .into_iter()
+            .filter(|d| d.name() == "width")
+            .map(|d| d.value().clone())
+            .collect::<Vec<_>>();
+        let expected = [1, 2, 4, 5].iter().copied().map(Value::AbsoluteLength);
+        assert_eq!(widths, expected.collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn parse_important() {
+        let sheet = ".a { width: 1 !important; color: red !important; height: 2; }"
+            .parse::<Stylesheet>()
+            .unwrap();
+        let declarations = sheet.rules()[0]
+            .declarations()
+            .iter()
+            .map(|d| (d.name(), d.value().clone(), d.important()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            declarations,
+            vec![
+                ("width", Value::AbsoluteLength(1), true),
+                ("color", Value::Keyword("red".to_string()), true),
+                ("height", Value::AbsoluteLength(2), false),
+            ]
+        );
+    }
+}
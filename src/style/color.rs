@@ -0,0 +1,121 @@
+//! Colors, set by `color` for text and by the `border-*-color` properties.
+
+/// A color: one of the eight basic terminal colors, by name, or an RGB
+/// color written as `#rgb` or `#rrggbb`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    Rgb(u8, u8, u8),
+}
+
+/// How colors are written to a terminal.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Without color.
+    #[default]
+    None,
+    /// With the eight basic colors, which RGB colors are rounded to.
+    Basic,
+    /// With 24-bit colors, which most modern terminals support.
+    TrueColor,
+}
+
+const BASIC: [(Color, &str, (u8, u8, u8)); 8] = [
+    (Color::Black, "black", (0, 0, 0)),
+    (Color::Red, "red", (255, 0, 0)),
+    (Color::Green, "green", (0, 255, 0)),
+    (Color::Yellow, "yellow", (255, 255, 0)),
+    (Color::Blue, "blue", (0, 0, 255)),
+    (Color::Magenta, "magenta", (255, 0, 255)),
+    (Color::Cyan, "cyan", (0, 255, 255)),
+    (Color::White, "white", (255, 255, 255)),
+];
+
+impl Color {
+    /// The color a keyword names, or `None` if it isn't one.
+    pub fn parse(keyword: &str) -> Option<Color> {
+        if let Some(hex) = keyword.strip_prefix('#') {
+            let digits = hex
+                .chars()
+                .map(|c| c.to_digit(16).map(|d| d as u8))
+                .collect::<Option<Vec<_>>>()?;
+            return match digits[..] {
+                [r, g, b] => Some(Color::Rgb(r * 17, g * 17, b * 17)),
+                [r1, r2, g1, g2, b1, b2] => {
+                    Some(Color::Rgb(r1 * 16 + r2, g1 * 16 + g2, b1 * 16 + b2))
+                }
+                _ => None,
+            };
+        }
+        BASIC
+            .iter()
+            .find(|(_, name, _)| name.eq_ignore_ascii_case(keyword))
+            .map(|&(color, ..)| color)
+    }
+
+    pub fn rgb(&self) -> (u8, u8, u8) {
+        match self {
+            Color::Rgb(r, g, b) => (*r, *g, *b),
+            basic => BASIC
+                .iter()
+                .find(|(c, ..)| c == basic)
+                .map_or((0, 0, 0), |&(.., rgb)| rgb),
+        }
+    }
+
+    /// The parameters of the escape sequence that sets text to this color,
+    /// or `None` in [`ColorMode::None`].
+    pub fn sgr(&self, mode: ColorMode) -> Option<String> {
+        match mode {
+            ColorMode::None => None,
+            ColorMode::Basic => {
+                // The basic color nearest to this one.
+                let (r, g, b) = self.rgb();
+                let distance = |(r2, g2, b2): (u8, u8, u8)| {
+                    let d = |a: u8, b: u8| (i32::from(a) - i32::from(b)).pow(2);
+                    d(r, r2) + d(g, g2) + d(b, b2)
+                };
+                let (index, _) = BASIC
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, &(_, _, rgb))| distance(rgb))?;
+                Some(format!("{}", 30 + index))
+            }
+            ColorMode::TrueColor => {
+                let (r, g, b) = self.rgb();
+                Some(format!("38;2;{};{};{}", r, g, b))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parse_and_write() {
+        assert_eq!(Color::parse("Red"), Some(Color::Red));
+        assert_eq!(Color::parse("#0f8"), Some(Color::Rgb(0, 255, 136)));
+        assert_eq!(Color::parse("#102030"), Some(Color::Rgb(16, 32, 48)));
+        assert_eq!(Color::parse("#1234"), None);
+        assert_eq!(Color::parse("teal"), None);
+
+        let sgr = |color: &str, mode| Color::parse(color).unwrap().sgr(mode);
+        assert_eq!(sgr("blue", ColorMode::Basic), Some("34".to_string()));
+        assert_eq!(sgr("#e01010", ColorMode::Basic), Some("31".to_string()));
+        assert_eq!(
+            sgr("#e01010", ColorMode::TrueColor),
+            Some("38;2;224;16;16".to_string())
+        );
+        assert_eq!(sgr("blue", ColorMode::None), None);
+    }
+}
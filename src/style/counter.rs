@@ -0,0 +1,250 @@
+//! Counters, which `counter-reset` and `counter-increment` set and
+//! `counter()`, `counters()` and list markers show.
+
+/// How a counter's value is written, set for list markers by
+/// `list-style-type`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListStyleType {
+    None,
+    Disc,
+    Circle,
+    Square,
+    Decimal,
+    LowerAlpha,
+    LowerRoman,
+    /// A string written in place of the value.
+    String(String),
+}
+
+/// Where a list item's marker is placed, set by `list-style-position`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum ListStylePosition {
+    /// Hanging before the item's first line, in the space left by its
+    /// list's padding.
+    #[default]
+    Outside,
+    /// At the start of the item's first line, like a `::before`
+    /// pseudo-element.
+    Inside,
+}
+
+impl ListStyleType {
+    /// `value` written in this style. Values that the alphabetic and roman
+    /// styles can't write are written as decimals.
+    pub fn format(&self, value: i32) -> String {
+        match self {
+            ListStyleType::None => String::new(),
+            ListStyleType::Disc => String::from("•"),
+            ListStyleType::Circle => String::from("◦"),
+            ListStyleType::Square => String::from("▪"),
+            ListStyleType::Decimal => value.to_string(),
+            ListStyleType::LowerAlpha => alphabetic(value).unwrap_or_else(|| value.to_string()),
+            ListStyleType::LowerRoman => roman(value).unwrap_or_else(|| value.to_string()),
+            ListStyleType::String(s) => s.clone(),
+        }
+    }
+
+    /// The marker of a list item whose `list-item` counter is `value`:
+    /// a symbol followed by a space, a number followed by `. `, or the
+    /// string as it is written.
+    pub fn marker(&self, value: i32) -> Option<String> {
+        match self {
+            ListStyleType::None => None,
+            ListStyleType::String(s) => Some(s.clone()),
+            ListStyleType::Disc | ListStyleType::Circle | ListStyleType::Square => {
+                Some(format!("{} ", self.format(value)))
+            }
+            _ => Some(format!("{}. ", self.format(value))),
+        }
+    }
+}
+
+/// `a` to `z`, then `aa`, `ab` and so on.
+fn alphabetic(value: i32) -> Option<String> {
+    if value < 1 {
+        return None;
+    }
+    let mut letters = Vec::new();
+    let mut n = value;
+    while n > 0 {
+        n -= 1;
+        letters.push((b'a' + (n % 26) as u8) as char);
+        n /= 26;
+    }
+    Some(letters.iter().rev().collect())
+}
+
+fn roman(value: i32) -> Option<String> {
+    const NUMERALS: [(i32, &str); 13] = [
+        (1000, "m"),
+        (900, "cm"),
+        (500, "d"),
+        (400, "cd"),
+        (100, "c"),
+        (90, "xc"),
+        (50, "l"),
+        (40, "xl"),
+        (10, "x"),
+        (9, "ix"),
+        (5, "v"),
+        (4, "iv"),
+        (1, "i"),
+    ];
+    if !(1..4000).contains(&value) {
+        return None;
+    }
+    let mut out = String::new();
+    let mut n = value;
+    for &(size, numeral) in &NUMERALS {
+        while n >= size {
+            out.push_str(numeral);
+            n -= size;
+        }
+    }
+    Some(out)
+}
+
+/// The counters in scope while visiting the elements of a document in order.
+///
+/// A counter reset by an element is in scope for the element and its
+/// descendants, and for its following siblings and theirs, until a later
+/// sibling resets it again.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    /// Every counter in scope, outermost first, with the depth of the
+    /// element that reset it.
+    counters: Vec<(String, i32, usize)>,
+    /// The depth of the elements being visited.
+    depth: usize,
+}
+
+impl Counters {
+    /// Apply an element's `counter-reset`, then its `counter-increment`.
+    pub(crate) fn update(&mut self, reset: &[(String, i32)], increment: &[(String, i32)]) {
+        for (name, value) in reset {
+            self.reset(name, *value);
+        }
+        for (name, by) in increment {
+            // Incrementing a counter that isn't in scope resets it first.
+            if !self.counters.iter().any(|(n, ..)| n == name) {
+                self.reset(name, 0);
+            }
+            if let Some(counter) = self.counters.iter_mut().rev().find(|(n, ..)| n == name) {
+                // Counters stop at the largest and smallest values rather
+                // than overflowing.
+                counter.1 = counter.1.saturating_add(*by);
+            }
+        }
+    }
+
+    fn reset(&mut self, name: &str, value: i32) {
+        let depth = self.depth;
+        match self
+            .counters
+            .iter_mut()
+            .find(|(n, _, d)| n == name && *d == depth)
+        {
+            Some(sibling) => sibling.1 = value,
+            None => self.counters.push((String::from(name), value, depth)),
+        }
+    }
+
+    /// Start visiting the children of the element last updated.
+    pub(crate) fn enter(&mut self) {
+        self.depth += 1;
+    }
+
+    /// Finish visiting an element's children, ending the scope of the
+    /// counters they reset.
+    pub(crate) fn leave(&mut self) {
+        let depth = self.depth;
+        self.counters.retain(|&(_, _, d)| d < depth);
+        self.depth -= 1;
+    }
+
+    /// The value of the innermost counter called `name`, or zero if there
+    /// isn't one.
+    pub(crate) fn value(&self, name: &str) -> i32 {
+        self.values(name).last().copied().unwrap_or(0)
+    }
+
+    /// The values of every counter called `name`, outermost first.
+    pub(crate) fn values(&self, name: &str) -> Vec<i32> {
+        self.counters
+            .iter()
+            .filter(|(n, ..)| n == name)
+            .map(|&(_, value, _)| value)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn styles() {
+        let format = |style: ListStyleType, values: &[i32]| {
+            values
+                .iter()
+                .map(|&v| style.format(v))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        assert_eq!(
+            format(ListStyleType::LowerAlpha, &[1, 26, 27, 52, 703, 0]),
+            "a z aa az aaa 0"
+        );
+        assert_eq!(
+            format(ListStyleType::LowerRoman, &[1, 4, 9, 14, 1994, 4000]),
+            "i iv ix xiv mcmxciv 4000"
+        );
+        assert_eq!(ListStyleType::Decimal.marker(3), Some("3. ".to_string()));
+        assert_eq!(ListStyleType::Square.marker(3), Some("▪ ".to_string()));
+        assert_eq!(
+            ListStyleType::String("-> ".to_string()).marker(3),
+            Some("-> ".to_string())
+        );
+        assert_eq!(ListStyleType::None.marker(3), None);
+    }
+
+    #[test]
+    fn scopes() {
+        let name = |n: &str| vec![(n.to_string(), 1)];
+        let mut counters = Counters::default();
+        // <ol><li><ol><li/><li/></ol></li><li/></ol>, where lists reset
+        // `item` and items increment it.
+        counters.update(&[("item".to_string(), 0)], &[]);
+        counters.enter();
+        counters.update(&[], &name("item"));
+        assert_eq!(counters.values("item"), vec![1]);
+        counters.enter();
+        counters.update(&[("item".to_string(), 0)], &[]);
+        counters.enter();
+        counters.update(&[], &name("item"));
+        counters.update(&[], &name("item"));
+        assert_eq!(counters.values("item"), vec![1, 2]);
+        counters.leave();
+        counters.leave();
+        counters.update(&[], &name("item"));
+        assert_eq!(counters.values("item"), vec![2]);
+        counters.leave();
+
+        // A counter that is incremented without being reset starts at zero,
+        // and a sibling's reset replaces it rather than nesting inside it.
+        counters.enter();
+        counters.update(&[], &[("n".to_string(), 5)]);
+        counters.update(&[("n".to_string(), 1)], &[]);
+        assert_eq!(counters.values("n"), vec![1]);
+        counters.leave();
+        assert_eq!(counters.value("n"), 0);
+
+        counters.update(&[("big".to_string(), i32::MAX - 1)], &[]);
+        counters.update(&[], &[("big".to_string(), i32::MAX)]);
+        counters.update(&[], &[("big".to_string(), i32::MAX)]);
+        assert_eq!(counters.value("big"), i32::MAX);
+        counters.update(&[("big".to_string(), i32::MIN)], &[("big".to_string(), -1)]);
+        assert_eq!(counters.value("big"), i32::MIN);
+    }
+}
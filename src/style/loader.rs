@@ -0,0 +1,390 @@
+use super::*;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+/// Somewhere stylesheets can be loaded from by name, such as the `href` of a
+/// `<link rel="stylesheet">` element.
+pub trait Loader {
+    /// Return the text of the stylesheet called `href`.
+    fn load(&self, href: &str) -> Result<String, String>;
+}
+
+/// Loads stylesheets from files, with relative paths resolved against a base
+/// directory.
+///
+/// Only files inside the base directory can be loaded, so that markup can't
+/// read other files with `<link>` or `@import`. Absolute paths, and paths
+/// that lead out of the directory with `..` or symbolic links, are errors.
+#[derive(Debug, Clone)]
+pub struct FileLoader {
+    base: PathBuf,
+}
+
+impl FileLoader {
+    pub fn new(base: impl Into<PathBuf>) -> Self {
+        FileLoader { base: base.into() }
+    }
+}
+
+impl Loader for FileLoader {
+    fn load(&self, href: &str) -> Result<String, String> {
+        let outside = || format!("{}: outside {}", href, self.base.display());
+        let relative = Path::new(href)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        if !relative {
+            return Err(outside());
+        }
+        let path = self.base.join(href);
+        // Files that don't exist are reported when they are read.
+        if let (Ok(base), Ok(target)) = (self.base.canonicalize(), path.canonicalize()) {
+            if !target.starts_with(base) {
+                return Err(outside());
+            }
+        }
+        std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// Loads stylesheets from a fixed set of in-memory sources, and nothing
+/// else.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLoader {
+    sources: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, href: impl Into<String>, source: impl Into<String>) -> Self {
+        self.insert(href, source);
+        self
+    }
+
+    pub fn insert(&mut self, href: impl Into<String>, source: impl Into<String>) {
+        self.sources.insert(href.into(), source.into());
+    }
+}
+
+impl Loader for MemoryLoader {
+    fn load(&self, href: &str) -> Result<String, String> {
+        self.sources
+            .get(href)
+            .cloned()
+            .ok_or_else(|| format!("{}: no such stylesheet", href))
+    }
+}
+
+impl Stylesheet {
+    /// Load the stylesheet called `href`, along with the stylesheets it
+    /// imports.
+    pub fn load(href: &str, loader: &dyn Loader) -> Result<Stylesheet, String> {
+        load(href, loader, &mut Vec::new())
+    }
+
+    /// Replace the stylesheet's `@import`s with the rules of the stylesheets
+    /// they name, loaded with `loader`.
+    ///
+    /// Imported rules come before the stylesheet's own, in the order they
+    /// were imported, so the importing stylesheet wins over them. In merged
+    /// stylesheets, they come before the rules of the stylesheet that
+    /// imported them. A stylesheet that ends up importing itself is an error.
+    pub fn resolve_imports(self, loader: &dyn Loader) -> Result<Stylesheet, String> {
+        resolve(self, None, loader, &mut Vec::new())
+    }
+
+    /// The stylesheet for a document: the `external` stylesheets, followed by
+    /// the contents of each `<style>` element and `<link rel="stylesheet">`
+    /// in the document, in document order, with all their imports resolved.
+    ///
+    /// Later rules win over earlier rules of the same specificity, so the
+    /// document's own styles can refine the external ones. Spans of rules
+    /// from a `<style>` element are relative to the start of its text.
+    pub fn for_document(
+        root: &Node,
+        external: &[Stylesheet],
+        loader: &dyn Loader,
+    ) -> Result<Stylesheet, String> {
+        let mut stylesheet = Stylesheet::default();
+        for sheet in external {
+            stylesheet.extend(sheet.clone().resolve_imports(loader)?);
+        }
+        for node in std::iter::once(root).chain(root.descendants()) {
+            match embedded_stylesheet(node) {
+                Some(Embedded::Text(source)) => {
+                    let sheet = source.parse::<Stylesheet>()?;
+                    stylesheet.extend(sheet.resolve_imports(loader)?);
+                }
+                Some(Embedded::Link(href)) => stylesheet.extend(Stylesheet::load(href, loader)?),
+                None => {}
+            }
+        }
+        Ok(stylesheet)
+    }
+}
+
+/// Load `href`, where `stack` holds the stylesheets importing it.
+fn load(href: &str, loader: &dyn Loader, stack: &mut Vec<String>) -> Result<Stylesheet, String> {
+    let href = &normalize(href);
+    if stack.iter().any(|h| h == href) {
+        return Err(format!("{} -> {}: import cycle", stack.join(" -> "), href));
+    }
+    let stylesheet = loader
+        .load(href)?
+        .parse::<Stylesheet>()
+        .map_err(|e| format!("{}: {}", href, e))?;
+    stack.push(href.to_string());
+    let stylesheet = resolve(stylesheet, Some(href), loader, stack);
+    stack.pop();
+    stylesheet
+}
+
+/// Resolve the imports of `stylesheet`, which was loaded from `base`.
+fn resolve(
+    stylesheet: Stylesheet,
+    base: Option<&str>,
+    loader: &dyn Loader,
+    stack: &mut Vec<String>,
+) -> Result<Stylesheet, String> {
+    let mut rules = Vec::new();
+    let mut own = stylesheet.rules.into_iter();
+    // How many of the stylesheet's own rules have been added.
+    let mut added = 0;
+    for import in stylesheet.imports {
+        // Imports are in order of position, so this adds the rules that
+        // come before this import.
+        let before = import.position.saturating_sub(added);
+        rules.extend(own.by_ref().take(before));
+        added += before;
+        let imported = load(&relative_href(base, &import.href), loader, stack)?;
+        rules.extend(imported.rules.into_iter().map(|mut rule| {
+            if let Some(ref query) = import.media {
+                rule.nest_in(query);
+            }
+            rule
+        }));
+    }
+    rules.extend(own);
+    Ok(Stylesheet {
+        rules,
+        imports: Vec::new(),
+    })
+}
+
+/// Resolve `href` against the directory of the stylesheet that imports it.
+fn relative_href(base: Option<&str>, href: &str) -> String {
+    let directory = base.and_then(|base| base.rfind('/').map(|slash| &base[..=slash]));
+    match directory {
+        Some(directory) if !href.starts_with('/') => format!("{}{}", directory, href),
+        _ => href.to_string(),
+    }
+}
+
+/// Remove `.` segments from `href`, and `..` segments along with the segment
+/// before them, so that every way of writing a path compares equal.
+fn normalize(href: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in href.split('/') {
+        match segment {
+            "." => {}
+            ".." if segments.last().is_some_and(|&s| s != ".." && !s.is_empty()) => {
+                segments.pop();
+            }
+            // Repeated slashes, but not a leading one.
+            "" if !segments.is_empty() => {}
+            _ => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
+
+enum Embedded<'a> {
+    Text(String),
+    Link(&'a str),
+}
+
+/// The stylesheet a node carries or links to, if any.
+fn embedded_stylesheet(node: &Node) -> Option<Embedded<'_>> {
+    match node.tag_name() {
+        Some(tag) if tag.eq_ignore_ascii_case("style") => Some(Embedded::Text(
+            node.children().filter_map(Node::text).collect::<String>(),
+        )),
+        Some(tag) if tag.eq_ignore_ascii_case("link") => {
+            let is_stylesheet = node.attribute("rel").is_some_and(|rel| {
+                rel.split_whitespace()
+                    .any(|r| r.eq_ignore_ascii_case("stylesheet"))
+            });
+            match node.attribute("href") {
+                Some(href) if is_stylesheet => Some(Embedded::Link(href)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn embedded_and_linked_stylesheets() {
+        let html = r#"<div>
+            <link rel="stylesheet" href="base.tss">
+            <link rel="icon" href="missing.png">
+            <style>
+                .a { height: 2; }
+            </style>
+            <div class="a"></div>
+        </div>"#;
+        let root = html.parse::<Node>().unwrap();
+        let loader = MemoryLoader::new().with("base.tss", ".a { display: block; }");
+        let external = ".b { width: 3; }".parse::<Stylesheet>().unwrap();
+        let stylesheet = Stylesheet::for_document(&root, &[external], &loader).unwrap();
+
+        let names = stylesheet
+            .rules()
+            .iter()
+            .flat_map(|r| r.declarations())
+            .map(Declaration::name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["width", "display", "height"]);
+    }
+
+    #[test]
+    fn missing_stylesheet() {
+        let root = "<link rel=\"stylesheet\" href=\"nope.tss\">"
+            .parse::<Node>()
+            .unwrap();
+        let err = Stylesheet::for_document(&root, &[], &MemoryLoader::new()).unwrap_err();
+        assert_eq!(err, "nope.tss: no such stylesheet");
+    }
+
+    #[test]
+    fn imports() {
+        let loader = MemoryLoader::new()
+            .with("app.tss", "@import \"theme/base.tss\";\n.a { width: 3; }")
+            .with(
+                "theme/base.tss",
+                "@import 'colors.tss' (min-width: 100);\n.a { width: 2; }",
+            )
+            .with("theme/colors.tss", ".a { width: 1; }");
+        let stylesheet = Stylesheet::load("app.tss", &loader).unwrap();
+        assert!(stylesheet.imports().is_empty());
+        let rules = stylesheet
+            .rules()
+            .iter()
+            .map(|r| (r.declarations()[0].value().clone(), r.media().cloned()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rules,
+            vec![
+                (Value::AbsoluteLength(1), Some(MediaQuery::MinWidth(100))),
+                (Value::AbsoluteLength(2), None),
+                (Value::AbsoluteLength(3), None),
+            ]
+        );
+
+        let loader = MemoryLoader::new()
+            .with("a.tss", "@import \"b.tss\";")
+            .with("b.tss", "@import \"a.tss\";");
+        assert_eq!(
+            Stylesheet::load("a.tss", &loader).unwrap_err(),
+            "a.tss -> b.tss -> a.tss: import cycle"
+        );
+        assert!(".a { }\n@import \"b.tss\";".parse::<Stylesheet>().is_err());
+
+        // Paths are normalized before they are compared.
+        let loader = MemoryLoader::new()
+            .with("a.tss", "@import \"./a.tss\";")
+            .with("x/b.tss", "@import \"../x/./b.tss\";");
+        assert_eq!(
+            Stylesheet::load("a.tss", &loader).unwrap_err(),
+            "a.tss -> a.tss: import cycle"
+        );
+        assert_eq!(
+            Stylesheet::load("./x//b.tss", &loader).unwrap_err(),
+            "x/b.tss -> x/b.tss: import cycle"
+        );
+        assert_eq!(normalize("/a/../../b/./c"), "/../b/c");
+        assert_eq!(normalize("../a/../b"), "../b");
+    }
+
+    /// A directory that is removed when dropped, even if the test fails.
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn files_outside_the_base_directory() {
+        let dir =
+            TempDir(std::env::temp_dir().join(format!("text_tree-loader-{}", std::process::id())));
+        let base = dir.0.join("styles");
+        std::fs::create_dir_all(&base).unwrap();
+        std::fs::write(base.join("a.tss"), ".a { width: 1; }").unwrap();
+        std::fs::write(dir.0.join("secret.tss"), ".a { width: 2; }").unwrap();
+        let loader = FileLoader::new(&base);
+
+        assert_eq!(loader.load("./a.tss"), Ok(".a { width: 1; }".to_string()));
+        let secret = dir.0.join("secret.tss");
+        for href in [
+            "../secret.tss",
+            "x/../../secret.tss",
+            &secret.to_string_lossy(),
+        ] {
+            assert!(
+                loader.load(href).unwrap_err().contains("outside"),
+                "{}",
+                href
+            );
+        }
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&secret, base.join("link.tss")).unwrap();
+            assert!(loader.load("link.tss").unwrap_err().contains("outside"));
+        }
+    }
+
+    #[test]
+    fn merge_then_resolve() {
+        let loader = MemoryLoader::new()
+            .with("base-theme.tss", ".a { width: 1; }")
+            .with("tool-theme.tss", ".a { width: 3; }");
+        let base = "@import \"base-theme.tss\";\n.a { width: 2; }"
+            .parse::<Stylesheet>()
+            .unwrap();
+        let tool = "@import \"tool-theme.tss\";\n.a { width: 4; }"
+            .parse::<Stylesheet>()
+            .unwrap();
+        let widths = base
+            .merge(tool)
+            .resolve_imports(&loader)
+            .unwrap()
+            .rules()
+            .iter()
+            .map(|r| r.declarations()[0].value().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            widths,
+            (1..=4).map(Value::AbsoluteLength).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn merge() {
+        let base = ".a { width: 1; }".parse::<Stylesheet>().unwrap();
+        let tool = "@import \"extra.tss\";\n.a { width: 2; }"
+            .parse::<Stylesheet>()
+            .unwrap();
+        let merged = base.merge(tool);
+        assert_eq!(merged.rules().len(), 2);
+        assert_eq!(merged.imports()[0].href(), "extra.tss");
+    }
+}
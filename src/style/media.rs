@@ -0,0 +1,125 @@
+/// The area a document is shown in, which `@media` queries are tested
+/// against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Viewport {
+    /// The width, in columns.
+    pub width: i32,
+    /// The height, in rows, or `None` if the document can be as tall as it
+    /// needs.
+    pub height: Option<i32>,
+}
+
+impl Viewport {
+    pub fn new(width: i32) -> Self {
+        Viewport {
+            width,
+            height: None,
+        }
+    }
+
+    pub fn with_height(self, height: i32) -> Self {
+        Viewport {
+            height: Some(height),
+            ..self
+        }
+    }
+}
+
+/// An 80 column terminal of any height.
+impl Default for Viewport {
+    fn default() -> Self {
+        Viewport::new(80)
+    }
+}
+
+/// The condition of an `@media` rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MediaQuery {
+    MinWidth(i32),
+    MaxWidth(i32),
+    MinHeight(i32),
+    MaxHeight(i32),
+    Not(Box<MediaQuery>),
+    And(Vec<MediaQuery>),
+    /// Written with `or`, or as a comma-separated list of queries.
+    Or(Vec<MediaQuery>),
+}
+
+impl MediaQuery {
+    pub fn matches(&self, viewport: &Viewport) -> bool {
+        match self {
+            MediaQuery::MinWidth(w) => viewport.width >= *w,
+            MediaQuery::MaxWidth(w) => viewport.width <= *w,
+            // A viewport without a height is taller than any height.
+            MediaQuery::MinHeight(h) => viewport.height.unwrap_or(i32::MAX) >= *h,
+            MediaQuery::MaxHeight(h) => viewport.height.is_some_and(|height| height <= *h),
+            MediaQuery::Not(query) => !query.matches(viewport),
+            MediaQuery::And(queries) => queries.iter().all(|q| q.matches(viewport)),
+            MediaQuery::Or(queries) => queries.iter().any(|q| q.matches(viewport)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::style::Stylesheet;
+    use pretty_assertions::assert_eq;
+
+    fn media(tss: &str) -> Vec<Option<MediaQuery>> {
+        let stylesheet = tss.parse::<Stylesheet>().unwrap();
+        stylesheet
+            .rules()
+            .iter()
+            .map(|r| r.media().cloned())
+            .collect()
+    }
+
+    #[test]
+    fn parse_media_rules() {
+        let queries = media(
+            ".a { width: 1; }\n\
+             @media (min-width: 100) and (max-width: 200) {\n\
+                 .b { width: 2; }\n\
+                 @media not (min-height: 30) { .c { width: 3; } }\n\
+             }\n\
+             @media (max-width: 59), ((min-height: 10) or (max-height: 5)) { .d { width: 4; } }\n\
+             .e { width: 5; }",
+        );
+        let range = MediaQuery::And(vec![MediaQuery::MinWidth(100), MediaQuery::MaxWidth(200)]);
+        assert_eq!(
+            queries,
+            vec![
+                None,
+                Some(range.clone()),
+                Some(MediaQuery::And(vec![
+                    range,
+                    MediaQuery::Not(Box::new(MediaQuery::MinHeight(30))),
+                ])),
+                Some(MediaQuery::Or(vec![
+                    MediaQuery::MaxWidth(59),
+                    MediaQuery::Or(vec![MediaQuery::MinHeight(10), MediaQuery::MaxHeight(5)]),
+                ])),
+                None,
+            ]
+        );
+
+        assert!(
+            "@media (min-width: 1) and (max-width: 2) or (min-height: 3) { }"
+                .parse::<Stylesheet>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn evaluation() {
+        let query = MediaQuery::Or(vec![
+            MediaQuery::And(vec![MediaQuery::MinWidth(100), MediaQuery::MaxWidth(200)]),
+            MediaQuery::Not(Box::new(MediaQuery::MinHeight(30))),
+        ]);
+        assert!(query.matches(&Viewport::new(150)));
+        assert!(!query.matches(&Viewport::new(80)));
+        assert!(query.matches(&Viewport::new(80).with_height(20)));
+        assert!(!MediaQuery::MaxHeight(50).matches(&Viewport::new(80)));
+    }
+}
@@ -1,15 +1,58 @@
 use super::content_tree::*;
+use crate::span::{LineIndex, Span};
+mod cascade;
+mod color;
+mod counter;
+mod loader;
+mod media;
 mod parse;
+mod pseudo;
+mod var;
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+pub use cascade::{Cascade, Origin};
+pub use color::{Color, ColorMode};
+pub(crate) use counter::Counters;
+pub use counter::{ListStylePosition, ListStyleType};
+pub use loader::{FileLoader, Loader, MemoryLoader};
+pub use media::{MediaQuery, Viewport};
+pub use pseudo::{Nth, PseudoClass, PseudoElement, Subject};
+pub(crate) use var::{is_custom_property, resolve_variables};
+
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
 pub struct Stylesheet {
     pub(super) rules: Vec<Rule>,
+    /// `@import`s that haven't been loaded yet.
+    pub(super) imports: Vec<Import>,
 }
 
+/// An `@import` of another stylesheet.
 #[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Import {
+    pub(super) href: String,
+    /// The media query the imported rules apply in, if any.
+    pub(super) media: Option<MediaQuery>,
+    /// How many of the stylesheet's rules come before the imported ones,
+    /// which is more than zero once stylesheets are merged.
+    pub(super) position: usize,
+}
+
+#[derive(Debug, Eq, Clone)]
 pub struct Rule {
     pub(super) selectors: Vec<Selector>,
     pub(super) declarations: Vec<Declaration>,
+    /// The media query the rule is nested in, if any.
+    pub(super) media: Option<MediaQuery>,
+    pub(super) span: Span,
+}
+
+// Spans are ignored, so that parsed stylesheets compare equal to the same
+// stylesheet built by hand.
+impl PartialEq for Rule {
+    fn eq(&self, other: &Rule) -> bool {
+        self.selectors == other.selectors
+            && self.declarations == other.declarations
+            && self.media == other.media
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -17,16 +60,28 @@ pub struct Selector {
     //node_type: Option<String>,
     pub(super) id: Option<String>,
     pub(super) classes: Vec<String>,
+    pub(super) pseudo_classes: Vec<PseudoClass>,
+    pub(super) pseudo_element: Option<PseudoElement>,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, Eq, Clone)]
 pub struct Declaration {
     pub(super) name: String,
     pub(super) value: Value,
+    /// Whether the declaration was marked `!important`.
+    pub(super) important: bool,
+    pub(super) span: Span,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+impl PartialEq for Declaration {
+    fn eq(&self, other: &Declaration) -> bool {
+        self.name == other.name && self.value == other.value && self.important == other.important
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
 pub enum Border {
+    #[default]
     None,
     Light,
     Heavy,
@@ -38,11 +93,71 @@ pub enum DisplayKind {
     None,
     Inline,
     Block,
+    /// A block with a list marker.
+    ListItem,
+}
+
+/// How whitespace in text content is handled, set by `white-space`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum WhiteSpace {
+    /// Collapse runs of whitespace and wrap lines as needed.
+    #[default]
+    Normal,
+    /// Preserve all whitespace and never wrap.
+    Pre,
+    /// Preserve all whitespace, but wrap lines as needed.
+    PreWrap,
+    /// Collapse runs of whitespace, but never wrap.
+    NoWrap,
+    /// Collapse spaces but preserve newlines, and wrap lines as needed.
+    PreLine,
+}
+
+/// How inline content is placed within each line, set by `text-align`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum TextAlign {
+    #[default]
+    Left,
+    Right,
+    Center,
+    /// Spread the words of every line but the last of a paragraph across the
+    /// whole width, by widening the spaces between them.
+    Justify,
 }
 
-impl Default for Border {
-    fn default() -> Self {
-        Border::None
+/// Where the content of a block container with an explicit `height` is
+/// placed when it is shorter than the box, set by `align-content`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum AlignContent {
+    #[default]
+    Start,
+    Center,
+    End,
+}
+
+impl WhiteSpace {
+    /// Whether runs of spaces and tabs collapse into a single space.
+    pub fn collapses_spaces(&self) -> bool {
+        matches!(
+            self,
+            WhiteSpace::Normal | WhiteSpace::NoWrap | WhiteSpace::PreLine
+        )
+    }
+
+    /// Whether newlines in the source force a line break.
+    pub fn preserves_newlines(&self) -> bool {
+        matches!(
+            self,
+            WhiteSpace::Pre | WhiteSpace::PreWrap | WhiteSpace::PreLine
+        )
+    }
+
+    /// Whether lines may be broken to fit the containing block.
+    pub fn wraps(&self) -> bool {
+        matches!(
+            self,
+            WhiteSpace::Normal | WhiteSpace::PreWrap | WhiteSpace::PreLine
+        )
     }
 }
 
@@ -54,6 +169,24 @@ impl Border {
         }
     }
 }
+
+/// A part of the `content` of a pseudo-element.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ContentItem {
+    /// A quoted string.
+    Text(String),
+    /// `attr(name)`, the value of an attribute of the element, or nothing if
+    /// it doesn't have one.
+    Attr(String),
+    /// `counter(name)` or `counter(name, style)`, the value of the innermost
+    /// counter called `name`.
+    Counter(String, ListStyleType),
+    /// `counters(name, separator)` or `counters(name, separator, style)`,
+    /// the values of every counter called `name` in scope, outermost first,
+    /// joined by `separator`.
+    Counters(String, String, ListStyleType),
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Value {
     Keyword(String),
@@ -64,6 +197,24 @@ pub enum Value {
     //RelativeLength(f32),
     Border(Border),
     Display(DisplayKind),
+    WhiteSpace(WhiteSpace),
+    TextAlign(TextAlign),
+    AlignContent(AlignContent),
+    /// The generated content of a `::before` or `::after` pseudo-element.
+    Content(Vec<ContentItem>),
+    /// The counters named by `counter-reset` or `counter-increment`, with
+    /// the value to reset them to or increment them by.
+    Counters(Vec<(String, i32)>),
+    ListStyleType(ListStyleType),
+    ListStylePosition(ListStylePosition),
+    /// A value that refers to custom properties with `var()`, which is
+    /// parsed once they are substituted into it.
+    Unresolved {
+        text: String,
+        /// The shorthand the value was written for, if any, which the
+        /// substituted text is parsed as.
+        shorthand: Option<String>,
+    },
 }
 
 impl Value {
@@ -81,14 +232,26 @@ pub type Specificity = (usize, usize, usize);
 
 impl Selector {
     pub fn specificity(&self) -> Specificity {
-        (
-            self.id.iter().count(),
-            self.classes.len(),
-            0, //self.node_type.iter().count(),
-        )
+        self.pseudo_classes
+            .iter()
+            .map(PseudoClass::specificity)
+            .fold(
+                (
+                    self.id.iter().count(),
+                    self.classes.len(),
+                    // Pseudo-elements count as much as a type selector.
+                    self.pseudo_element.iter().count(), //+ self.node_type.iter().count(),
+                ),
+                |(a, b, c), (x, y, z)| (a + x, b + y, c + z),
+            )
     }
 
-    pub fn matches(&self, element: &ElementData) -> bool {
+    pub fn matches(&self, subject: &Subject) -> bool {
+        if self.pseudo_element != subject.pseudo_element {
+            return false;
+        }
+
+        let element = subject.element;
         //println!("checking if {:?} matches {:?}", self, element);
         if self.id.iter().any(|id| element.id != Some(id.to_string())) {
             //println!("id does not match");
@@ -104,6 +267,14 @@ impl Selector {
             return false;
         }
 
+        if self
+            .pseudo_classes
+            .iter()
+            .any(|pseudo_class| !pseudo_class.matches(subject))
+        {
+            return false;
+        }
+
         //println!("matches");
         true
     }
@@ -111,35 +282,165 @@ impl Selector {
 
 type MatchedRule<'a> = (Specificity, &'a Rule);
 
+impl Declaration {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    pub fn important(&self) -> bool {
+        self.important
+    }
+
+    /// Where this declaration was written in the stylesheet it was parsed
+    /// from.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
 impl Rule {
-    pub fn match_rule<'a, 'b>(&'a self, element: &'b ElementData) -> Option<MatchedRule<'a>> {
+    pub fn declarations(&self) -> &[Declaration] {
+        &self.declarations
+    }
+
+    /// Where this rule was written in the stylesheet it was parsed from,
+    /// from the start of its selector to its closing brace.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// The `@media` query that must match for the rule to apply.
+    pub fn media(&self) -> Option<&MediaQuery> {
+        self.media.as_ref()
+    }
+
+    /// Make the rule apply only where `query` also matches.
+    pub(super) fn nest_in(&mut self, query: &MediaQuery) {
+        self.media = Some(match self.media.take() {
+            Some(inner) => MediaQuery::And(vec![query.clone(), inner]),
+            None => query.clone(),
+        });
+    }
+
+    pub fn match_rule<'a>(&'a self, subject: &Subject) -> Option<MatchedRule<'a>> {
         self.selectors
             .iter()
-            .find(|selector| selector.matches(element))
+            .find(|selector| selector.matches(subject))
             .map(|selector| (selector.specificity(), self))
     }
 }
 
 impl Stylesheet {
-    pub fn matching_rules<'a, 'b>(&'a self, element: &'b ElementData) -> Vec<MatchedRule<'a>> {
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// The stylesheet's `@import`s that haven't been loaded yet, which
+    /// [`Stylesheet::resolve_imports`] replaces with the rules they import.
+    pub fn imports(&self) -> &[Import] {
+        &self.imports
+    }
+
+    /// Add the rules and imports of `other` after this stylesheet's own, so
+    /// that they win over rules of the same specificity.
+    ///
+    /// The imports of `other` stay between the two stylesheets' rules, so the
+    /// rules they import win over this stylesheet's too.
+    pub fn extend(&mut self, other: Stylesheet) {
+        let offset = self.rules.len();
+        self.rules.extend(other.rules);
+        self.imports
+            .extend(other.imports.into_iter().map(|import| Import {
+                position: import.position + offset,
+                ..import
+            }));
+    }
+
+    /// This stylesheet followed by `other`, as with [`Stylesheet::extend`].
+    pub fn merge(mut self, other: Stylesheet) -> Stylesheet {
+        self.extend(other);
+        self
+    }
+
+    /// The built-in rules applied beneath every author stylesheet, which
+    /// make block elements such as `div`, `p` and `h1` blocks and hide
+    /// `head`, `style` and `script`.
+    ///
+    /// Style a document with a [`Cascade`] that doesn't include them to leave
+    /// them out.
+    pub fn user_agent() -> Stylesheet {
+        include_str!("default.tss")
+            .parse()
+            .expect("the default stylesheet should parse")
+    }
+
+    pub fn matching_rules<'a>(&'a self, subject: &Subject) -> Vec<MatchedRule<'a>> {
         self.rules
             .iter()
-            .filter_map(|rule| rule.match_rule(element))
+            .filter_map(|rule| rule.match_rule(subject))
             .collect()
     }
 }
 
+impl Import {
+    pub fn href(&self) -> &str {
+        &self.href
+    }
+
+    pub fn media(&self) -> Option<&MediaQuery> {
+        self.media.as_ref()
+    }
+}
+
+impl std::str::FromStr for Selector {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use nom::Finish;
+        match parse::selector(s).finish() {
+            Ok((_remaining, selector)) => Ok(selector),
+            Err(e) => Err(nom::error::convert_error(s, e)),
+        }
+    }
+}
+
 impl std::str::FromStr for Stylesheet {
     type Err = String;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         use nom::Finish;
         match parse::stylesheet(s).finish() {
-            Ok((_remaining, stylesheet)) => Ok(stylesheet),
+            Ok((_remaining, mut stylesheet)) => {
+                let index = LineIndex::new(s);
+                for rule in &mut stylesheet.rules {
+                    rule.span.resolve(&index);
+                    for declaration in &mut rule.declarations {
+                        declaration.span.resolve(&index);
+                    }
+                }
+                Ok(stylesheet)
+            }
             Err(e) => Err(nom::error::convert_error(s, e)),
         }
     }
 }
 
+/// The longhands a single-valued shorthand expands to, for tests.
+#[cfg(test)]
+pub(crate) fn sides(shorthand: &str, value: Value) -> Vec<Declaration> {
+    ["top", "right", "bottom", "left"]
+        .iter()
+        .map(|side| Declaration {
+            name: format!("{}-{}", shorthand, side),
+            value: value.clone(),
+            important: false,
+            span: Span::default(),
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -161,122 +462,275 @@ mod tests {
                     selectors: vec![Selector {
                         id: Some("root".to_string()),
                         classes: vec![],
+                        pseudo_classes: vec![],
+                        pseudo_element: None,
                     }],
-                    declarations: vec![
-                        Declaration {
-                            name: "padding".to_string(),
-                            value: Value::AbsoluteLength(2),
-                        },
-                        Declaration {
-                            name: "margin".to_string(),
-                            value: Value::AbsoluteLength(3),
-                        },
-                    ],
+                    declarations: [
+                        sides("padding", Value::AbsoluteLength(2)),
+                        sides("margin", Value::AbsoluteLength(3)),
+                    ]
+                    .concat(),
+                    media: None,
+                    span: Span::default(),
                 },
                 Rule {
                     selectors: vec![Selector {
                         id: None,
                         classes: vec!["block".to_string()],
+                        pseudo_classes: vec![],
+                        pseudo_element: None,
                     }],
                     declarations: vec![Declaration {
                         name: "display".to_string(),
                         value: Value::Display(DisplayKind::Block),
+                        important: false,
+                        span: Span::default(),
                     }],
+                    media: None,
+                    span: Span::default(),
                 },
                 Rule {
                     selectors: vec![Selector {
                         id: None,
                         classes: vec!["class-a".to_string()],
+                        pseudo_classes: vec![],
+                        pseudo_element: None,
                     }],
-                    declarations: vec![
-                        Declaration {
-                            name: "padding".to_string(),
-                            value: Value::AbsoluteLength(2),
-                        },
-                        Declaration {
-                            name: "border".to_string(),
-                            value: Value::Border(Border::Light),
-                        },
-                        Declaration {
+                    declarations: [
+                        sides("padding", Value::AbsoluteLength(2)),
+                        sides("border", Value::Border(Border::Light)),
+                        vec![Declaration {
                             name: "height".to_string(),
                             value: Value::AbsoluteLength(12),
-                        },
-                    ],
+                            important: false,
+                            span: Span::default(),
+                        }],
+                    ]
+                    .concat(),
+                    media: None,
+                    span: Span::default(),
                 },
                 Rule {
                     selectors: vec![Selector {
                         id: None,
                         classes: vec!["class-b".to_string()],
+                        pseudo_classes: vec![],
+                        pseudo_element: None,
                     }],
-                    declarations: vec![
-                        Declaration {
-                            name: "width".to_string(),
-                            //value: Value::RelativeLength(0.5),
-                            value: Value::AbsoluteLength(20),
-                        },
-                        Declaration {
-                            name: "height".to_string(),
-                            value: Value::AbsoluteLength(2),
-                        },
-                        Declaration {
-                            name: "margin".to_string(),
-                            value: Value::Auto,
-                        },
-                    ],
+                    declarations: [
+                        vec![
+                            Declaration {
+                                name: "width".to_string(),
+                                //value: Value::RelativeLength(0.5),
+                                value: Value::AbsoluteLength(20),
+                                important: false,
+                                span: Span::default(),
+                            },
+                            Declaration {
+                                name: "height".to_string(),
+                                value: Value::AbsoluteLength(2),
+                                important: false,
+                                span: Span::default(),
+                            },
+                        ],
+                        sides("margin", Value::Auto),
+                    ]
+                    .concat(),
+                    media: None,
+                    span: Span::default(),
                 },
                 Rule {
                     selectors: vec![Selector {
                         id: None,
                         classes: vec!["class-c".to_string()],
+                        pseudo_classes: vec![],
+                        pseudo_element: None,
                     }],
                     declarations: vec![
                         Declaration {
                             name: "width".to_string(),
                             //value: Value::RelativeLength(0.5),
                             value: Value::AbsoluteLength(40),
+                            important: false,
+                            span: Span::default(),
                         },
                         Declaration {
                             name: "height".to_string(),
                             value: Value::AbsoluteLength(4),
+                            important: false,
+                            span: Span::default(),
                         },
                         Declaration {
                             name: "margin-left".to_string(),
                             value: Value::AbsoluteLength(6),
+                            important: false,
+                            span: Span::default(),
                         },
                         Declaration {
                             name: "margin-bottom".to_string(),
                             value: Value::AbsoluteLength(2),
+                            important: false,
+                            span: Span::default(),
                         },
                         Declaration {
                             name: "border-left".to_string(),
                             value: Value::Border(Border::Double),
+                            important: false,
+                            span: Span::default(),
                         },
                         Declaration {
                             name: "border-right".to_string(),
                             value: Value::Border(Border::Double),
+                            important: false,
+                            span: Span::default(),
                         },
                         Declaration {
                             name: "border-top".to_string(),
                             value: Value::Border(Border::Light),
+                            important: false,
+                            span: Span::default(),
                         },
                         Declaration {
                             name: "border-bottom".to_string(),
                             value: Value::Border(Border::Heavy),
+                            important: false,
+                            span: Span::default(),
                         },
                     ],
+                    media: None,
+                    span: Span::default(),
                 },
                 Rule {
                     selectors: vec![Selector {
                         id: None,
                         classes: vec!["class-d".to_string()],
+                        pseudo_classes: vec![],
+                        pseudo_element: None,
                     }],
                     declarations: vec![Declaration {
                         name: "padding-left".to_string(),
                         value: Value::AbsoluteLength(2),
+                        important: false,
+                        span: Span::default(),
                     }],
+                    media: None,
+                    span: Span::default(),
                 },
             ],
+            imports: Vec::new(),
         };
         assert_eq!(text.parse::<Stylesheet>(), Ok(stylesheet))
     }
+
+    #[test]
+    fn rule_and_declaration_spans() {
+        let text = include_str!("../../example.tss");
+        let stylesheet = text.parse::<Stylesheet>().unwrap();
+
+        let rule = &stylesheet.rules()[4];
+        assert_eq!(&text[rule.span().range()][..10], ".class-c {");
+        assert_eq!(rule.span().start.line, 22);
+
+        let declaration = &rule.declarations()[7];
+        assert_eq!(&text[declaration.span().range()], "border-bottom: heavy;");
+        assert_eq!(
+            (
+                declaration.span().start.line,
+                declaration.span().start.column
+            ),
+            (30, 5)
+        );
+    }
+
+    #[test]
+    fn shorthands() {
+        // The value each longhand ends up with, in declaration order.
+        let longhands = |tss: &str| {
+            let stylesheet = tss.parse::<Stylesheet>().unwrap();
+            let mut values = std::collections::BTreeMap::new();
+            for declaration in stylesheet.rules()[0].declarations() {
+                values.insert(declaration.name().to_string(), declaration.value().clone());
+            }
+            values.into_iter().collect::<Vec<_>>()
+        };
+        let len = Value::AbsoluteLength;
+        let name = |s: &str| s.to_string();
+
+        assert_eq!(
+            longhands(".a { margin-left: 9; margin: 1 auto 3; margin-top: 4; }"),
+            vec![
+                (name("margin-bottom"), len(3)),
+                (name("margin-left"), Value::Auto),
+                (name("margin-right"), Value::Auto),
+                (name("margin-top"), len(4)),
+            ]
+        );
+        assert_eq!(
+            longhands(".a { padding: 1 2; }"),
+            vec![
+                (name("padding-bottom"), len(1)),
+                (name("padding-left"), len(2)),
+                (name("padding-right"), len(2)),
+                (name("padding-top"), len(1)),
+            ]
+        );
+
+        let heavy = Value::Border(Border::Heavy);
+        let red = Value::Keyword(name("red"));
+        assert_eq!(
+            longhands(".a { border: heavy red; border-top: #f00 light; border-color: blue; }")
+                .into_iter()
+                .filter(|(n, _)| n.starts_with("border-top") || n.starts_with("border-left"))
+                .collect::<Vec<_>>(),
+            vec![
+                (name("border-left"), heavy),
+                (name("border-left-color"), Value::Keyword(name("blue"))),
+                (name("border-top"), Value::Border(Border::Light)),
+                (name("border-top-color"), Value::Keyword(name("blue"))),
+            ]
+        );
+        // Values a shorthand can't take are kept as keywords, like those of
+        // unknown properties.
+        assert_eq!(
+            longhands(".a { border: red; margin: 1 2 3 4 5; }"),
+            vec![
+                (name("border"), red),
+                (name("margin"), Value::Keyword(name("1 2 3 4 5"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn generated_content_values() {
+        let values = |tss: &str| {
+            let stylesheet = tss.parse::<Stylesheet>().unwrap();
+            stylesheet.rules()[0]
+                .declarations()
+                .iter()
+                .map(|d| d.value().clone())
+                .collect::<Vec<_>>()
+        };
+        let counters = |c: &[(&str, i32)]| {
+            Value::Counters(c.iter().map(|&(n, v)| (n.to_string(), v)).collect())
+        };
+        assert_eq!(
+            values(".a { counter-reset: a b -2; counter-increment: a 3 b; counter-reset: none; }"),
+            vec![
+                counters(&[("a", 0), ("b", -2)]),
+                counters(&[("a", 3), ("b", 1)]),
+                counters(&[]),
+            ]
+        );
+        assert_eq!(
+            values(
+                ".a { content: \"a\\\"b\" attr(title) counter(c, lower-roman) counters(c, '.'); }"
+            ),
+            vec![Value::Content(vec![
+                ContentItem::Text("a\"b".to_string()),
+                ContentItem::Attr("title".to_string()),
+                ContentItem::Counter("c".to_string(), ListStyleType::LowerRoman),
+                ContentItem::Counters("c".to_string(), ".".to_string(), ListStyleType::Decimal),
+            ])]
+        );
+    }
 }
@@ -1,4 +1,5 @@
 use super::*;
+use crate::span::Span;
 use nom::error::{context, VerboseError};
 use nom::{
     branch::*, bytes::complete::*, character::complete::*, combinator::*, multi::*, sequence::*,
@@ -10,91 +11,411 @@ pub type IResult<I, T> = nom::IResult<I, T, VerboseError<I>>;
 // == rule ===================================================================
 
 pub(super) fn stylesheet(input: &str) -> IResult<&str, Stylesheet> {
-    let (remaining, rules) = many1(skip_ws(rule))(input)?;
-    let stylesheet = Stylesheet { rules };
+    let (remaining, (imports, rules)) = terminated(
+        pair(many0(skip_ws(import)), rules),
+        preceded(multispace0, context("end of stylesheet", eof)),
+    )(input)?;
+    let stylesheet = Stylesheet { rules, imports };
     Ok((remaining, stylesheet))
 }
 
+/// An `@import "href";`, optionally followed by a media query. Imports must
+/// come before any rules.
+fn import(input: &str) -> IResult<&str, Import> {
+    context(
+        "@import",
+        map(
+            delimited(
+                pair(tag("@import"), multispace1),
+                pair(
+                    alt((
+                        delimited(char('"'), take_until("\""), char('"')),
+                        delimited(char('\''), take_until("'"), char('\'')),
+                    )),
+                    opt(preceded(multispace1, media_query_list)),
+                ),
+                pair(multispace0, char(';')),
+            ),
+            |(href, media)| Import {
+                href: String::from(href),
+                media,
+                position: 0,
+            },
+        ),
+    )(input)
+}
+
+/// Rules, with those in `@media` blocks flattened into the list.
+fn rules(input: &str) -> IResult<&str, Vec<Rule>> {
+    map(
+        many0(skip_ws(alt((map(rule, |rule| vec![rule]), media_rule)))),
+        |groups| groups.into_iter().flatten().collect(),
+    )(input)
+}
+
 fn rule(input: &str) -> IResult<&str, Rule> {
     let (remaining, (selector, declarations)) = context(
         "rule",
         pair(
             any_selector,
-            delimited(tag(" {"), many0(skip_ws(any_decl)), tag("}")),
+            delimited(
+                tag(" {"),
+                map(many0(skip_ws(any_decl)), |d| d.concat()),
+                preceded(multispace0, tag("}")),
+            ),
         ),
     )(input)?;
 
     let rule = Rule {
         selectors: vec![selector],
         declarations,
+        media: None,
+        span: Span::consumed(input, remaining),
     };
     Ok((remaining, rule))
 }
-// == declaration ============================================================
 
-fn any_decl(input: &str) -> IResult<&str, Declaration> {
+// == media ==================================================================
+
+fn media_rule(input: &str) -> IResult<&str, Vec<Rule>> {
+    let (remaining, (query, rules)) = context(
+        "@media rule",
+        pair(
+            delimited(
+                pair(tag("@media"), multispace1),
+                media_query_list,
+                multispace0,
+            ),
+            delimited(char('{'), rules, char('}')),
+        ),
+    )(input)?;
+
+    let rules = rules
+        .into_iter()
+        .map(|mut rule| {
+            rule.nest_in(&query);
+            rule
+        })
+        .collect();
+    Ok((remaining, rules))
+}
+
+fn media_query_list(input: &str) -> IResult<&str, MediaQuery> {
+    map(
+        separated_list1(skip_ws(char(',')), media_condition),
+        |mut queries| match queries.len() {
+            1 => queries.remove(0),
+            _ => MediaQuery::Or(queries),
+        },
+    )(input)
+}
+
+fn media_condition(input: &str) -> IResult<&str, MediaQuery> {
     context(
+        "media condition",
+        alt((
+            map(
+                preceded(pair(tag("not"), multispace1), media_in_parens),
+                |query| MediaQuery::Not(Box::new(query)),
+            ),
+            media_combination,
+        )),
+    )(input)
+}
+
+/// Conditions joined by `and`, or by `or`, which can't be mixed without
+/// parentheses.
+fn media_combination(input: &str) -> IResult<&str, MediaQuery> {
+    let (input, first) = media_in_parens(input)?;
+    let (input, and) = many0(preceded(keyword("and"), media_in_parens))(input)?;
+    if !and.is_empty() {
+        return Ok((input, MediaQuery::And(join(first, and))));
+    }
+    let (input, or) = many0(preceded(keyword("or"), media_in_parens))(input)?;
+    if !or.is_empty() {
+        return Ok((input, MediaQuery::Or(join(first, or))));
+    }
+    Ok((input, first))
+}
+
+fn join(first: MediaQuery, rest: Vec<MediaQuery>) -> Vec<MediaQuery> {
+    std::iter::once(first).chain(rest).collect()
+}
+
+fn media_in_parens(input: &str) -> IResult<&str, MediaQuery> {
+    delimited(
+        pair(char('('), multispace0),
+        alt((media_feature, media_condition)),
+        pair(multispace0, char(')')),
+    )(input)
+}
+
+fn media_feature(input: &str) -> IResult<&str, MediaQuery> {
+    let feature = |name, query: fn(i32) -> MediaQuery| {
+        map(
+            preceded(pair(tag(name), skip_ws(char(':'))), decimal),
+            query,
+        )
+    };
+    context(
+        "media feature",
+        alt((
+            feature("min-width", MediaQuery::MinWidth),
+            feature("max-width", MediaQuery::MaxWidth),
+            feature("min-height", MediaQuery::MinHeight),
+            feature("max-height", MediaQuery::MaxHeight),
+        )),
+    )(input)
+}
+
+/// A keyword between other tokens, such as `and`.
+fn keyword<'a>(word: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    delimited(multispace1, tag(word), multispace1)
+}
+// == declaration ============================================================
+
+const MARGINS: [&str; 4] = ["margin-top", "margin-right", "margin-bottom", "margin-left"];
+const PADDINGS: [&str; 4] = [
+    "padding-top",
+    "padding-right",
+    "padding-bottom",
+    "padding-left",
+];
+const BORDERS: [&str; 4] = ["border-top", "border-right", "border-bottom", "border-left"];
+const BORDER_COLORS: [&str; 4] = [
+    "border-top-color",
+    "border-right-color",
+    "border-bottom-color",
+    "border-left-color",
+];
+
+/// A declaration, as the longhand declarations it sets. Shorthands such as
+/// `margin` are expanded here, so that they override earlier longhands and
+/// are overridden by later ones.
+fn any_decl(input: &str) -> IResult<&str, Vec<Declaration>> {
+    let (remaining, mut declarations) = context(
         "any declaration",
         alt((
-            named_decl("width", abs_len),
-            named_decl("height", abs_len),
-            // margin
-            named_decl("margin", margin),
-            named_decl("margin-top", margin),
-            named_decl("margin-bottom", margin),
-            named_decl("margin-left", margin),
-            named_decl("margin-right", margin),
-            // padding
-            named_decl("padding", abs_len),
-            named_decl("padding-top", abs_len),
-            named_decl("padding-bottom", abs_len),
-            named_decl("padding-left", abs_len),
-            named_decl("padding-right", abs_len),
-            // border
-            named_decl("border", border),
-            named_decl("border-top", border),
-            named_decl("border-bottom", border),
-            named_decl("border-left", border),
-            named_decl("border-right", border),
-            // display
+            alt((named_decl("width", abs_len), named_decl("height", abs_len))),
+            alt((
+                shorthand("margin", box_sides(&MARGINS, margin)),
+                named_decl("margin-top", margin),
+                named_decl("margin-bottom", margin),
+                named_decl("margin-left", margin),
+                named_decl("margin-right", margin),
+            )),
+            alt((
+                shorthand("padding", box_sides(&PADDINGS, abs_len)),
+                named_decl("padding-top", abs_len),
+                named_decl("padding-bottom", abs_len),
+                named_decl("padding-left", abs_len),
+                named_decl("padding-right", abs_len),
+            )),
+            alt((
+                shorthand("border", border_sides(0..4)),
+                shorthand("border-top", border_sides(0..1)),
+                shorthand("border-right", border_sides(1..2)),
+                shorthand("border-bottom", border_sides(2..3)),
+                shorthand("border-left", border_sides(3..4)),
+                shorthand("border-style", box_sides(&BORDERS, border)),
+                shorthand("border-color", box_sides(&BORDER_COLORS, color)),
+            )),
             named_decl("display", display_kind),
-            unnamed_decl,
+            named_decl("white-space", white_space),
+            named_decl("text-align", text_align),
+            named_decl("align-content", align_content),
+            alt((
+                named_decl("content", content),
+                named_decl("counter-reset", counter_list(0)),
+                named_decl("counter-increment", counter_list(1)),
+                named_decl("list-style-type", list_style_type),
+                named_decl("list-style-position", list_style_position),
+            )),
+            map(unnamed_decl, expand_unresolved),
         )),
-    )(input)
+    )(input)?;
+    let span = Span::consumed(input, remaining);
+    for declaration in &mut declarations {
+        declaration.span = span;
+    }
+    Ok((remaining, declarations))
 }
 
 fn named_decl<'a>(
     name: &'static str,
     value: impl FnMut(&'a str) -> IResult<&str, Value>,
-) -> impl FnMut(&'a str) -> IResult<&str, Declaration> {
+) -> impl FnMut(&'a str) -> IResult<&str, Vec<Declaration>> {
+    shorthand(name, map(value, move |value| vec![(name, value)]))
+}
+
+/// A declaration of `name` that sets the longhands `longhands` parses.
+fn shorthand<'a>(
+    name: &'static str,
+    longhands: impl FnMut(&'a str) -> IResult<&str, Vec<(&'static str, Value)>>,
+) -> impl FnMut(&'a str) -> IResult<&str, Vec<Declaration>> {
     context(
         name,
         map(
-            delimited(preceded(tag(name), tag(": ")), value, tag(";")),
-            move |value| Declaration {
-                name: String::from(name),
-                value,
+            delimited(
+                preceded(tag(name), tag(": ")),
+                pair(longhands, important),
+                tag(";"),
+            ),
+            |(longhands, important)| {
+                longhands
+                    .into_iter()
+                    .map(|(name, value)| Declaration {
+                        name: String::from(name),
+                        value,
+                        important,
+                        span: Span::default(),
+                    })
+                    .collect()
             },
         ),
     )
 }
 
-fn unnamed_decl<'a>(input: &str) -> IResult<&str, Declaration> {
+/// One to four values for the sides of a box, in the order top, right,
+/// bottom, left. Missing values are copied from the opposite side.
+fn box_sides<'a>(
+    sides: &'static [&'static str; 4],
+    value: impl FnMut(&'a str) -> IResult<&str, Value>,
+) -> impl FnMut(&'a str) -> IResult<&str, Vec<(&'static str, Value)>> {
+    map_opt(separated_list1(space1, value), move |values| {
+        let (top, right, bottom, left) = match values.as_slice() {
+            [all] => (all, all, all, all),
+            [vertical, horizontal] => (vertical, horizontal, vertical, horizontal),
+            [top, horizontal, bottom] => (top, horizontal, bottom, horizontal),
+            [top, right, bottom, left] => (top, right, bottom, left),
+            _ => return None,
+        };
+        let values = [top, right, bottom, left];
+        Some(
+            sides
+                .iter()
+                .zip(values.iter())
+                .map(|(&side, &value)| (side, value.clone()))
+                .collect(),
+        )
+    })
+}
+
+/// A border style and an optional color, in either order, for the sides in
+/// `sides`.
+fn border_sides<'a>(
+    sides: std::ops::Range<usize>,
+) -> impl FnMut(&'a str) -> IResult<&str, Vec<(&'static str, Value)>> {
+    map(
+        alt((
+            pair(border, opt(preceded(space1, color))),
+            map(separated_pair(color, space1, border), |(color, style)| {
+                (style, Some(color))
+            }),
+        )),
+        move |(style, color)| {
+            let mut longhands = sides
+                .clone()
+                .map(|i| (BORDERS[i], style.clone()))
+                .collect::<Vec<_>>();
+            if let Some(color) = color {
+                longhands.extend(sides.clone().map(|i| (BORDER_COLORS[i], color.clone())));
+            }
+            longhands
+        },
+    )
+}
+
+/// The longhands set by a shorthand property.
+fn longhands(shorthand: &str) -> Option<Vec<&'static str>> {
+    if let Some(i) = BORDERS.iter().position(|&side| side == shorthand) {
+        return Some(vec![BORDERS[i], BORDER_COLORS[i]]);
+    }
+    Some(match shorthand {
+        "margin" => MARGINS.to_vec(),
+        "padding" => PADDINGS.to_vec(),
+        "border" => BORDERS.iter().chain(&BORDER_COLORS).copied().collect(),
+        "border-style" => BORDERS.to_vec(),
+        "border-color" => BORDER_COLORS.to_vec(),
+        _ => return None,
+    })
+}
+
+/// Give each longhand of a shorthand that refers to custom properties the
+/// unresolved value, to be parsed as the shorthand once substituted.
+fn expand_unresolved(declaration: Declaration) -> Vec<Declaration> {
+    match (&declaration.value, longhands(&declaration.name)) {
+        (Value::Unresolved { text, .. }, Some(longhands)) => longhands
+            .into_iter()
+            .map(|longhand| Declaration {
+                name: String::from(longhand),
+                value: Value::Unresolved {
+                    text: text.clone(),
+                    shorthand: Some(declaration.name.clone()),
+                },
+                important: declaration.important,
+                span: declaration.span,
+            })
+            .collect(),
+        _ => vec![declaration],
+    }
+}
+
+/// Parse `text` as the value of a declaration of `name`, as if it had been
+/// written in a rule, and return the value it gives `longhand`.
+pub(super) fn declaration_value(name: &str, longhand: &str, text: &str) -> Option<Value> {
+    let source = format!("{}: {};", name, text);
+    match any_decl(&source) {
+        Ok(("", declarations)) => declarations
+            .into_iter()
+            .find(|d| d.name == longhand)
+            .map(|d| d.value),
+        _ => None,
+    }
+}
+
+/// A declaration of a property without a parser of its own, including
+/// custom properties, or one whose value refers to custom properties.
+fn unnamed_decl(input: &str) -> IResult<&str, Declaration> {
     context(
         "declaration",
         map(
             terminated(
-                pair(terminated(identifier, tag(": ")), take_until(";")),
+                pair(
+                    terminated(alt((custom_property, identifier)), tag(": ")),
+                    take_until(";"),
+                ),
                 tag(";"),
             ),
-            |(name, value)| Declaration {
-                value: Value::Keyword(String::from(value)),
-                name: String::from(name),
+            |(name, value): (&str, &str)| {
+                let (value, important) = match value.trim_end().strip_suffix("!important") {
+                    Some(value) => (value.trim_end(), true),
+                    None => (value, false),
+                };
+                let value = if value.contains("var(") {
+                    Value::Unresolved {
+                        text: String::from(value),
+                        shorthand: None,
+                    }
+                } else {
+                    Value::Keyword(String::from(value))
+                };
+                Declaration {
+                    value,
+                    name: String::from(name),
+                    important,
+                    span: Span::default(),
+                }
             },
         ),
     )(input)
 }
 
+/// An optional `!important` after a declaration's value.
+fn important(input: &str) -> IResult<&str, bool> {
+    map(opt(preceded(space0, tag("!important"))), |i| i.is_some())(input)
+}
+
 fn margin(input: &str) -> IResult<&str, Value> {
     context("margin", alt((abs_len, value(Value::Auto, tag("auto")))))(input)
 }
@@ -106,6 +427,7 @@ fn display_kind(input: &str) -> IResult<&str, Value> {
             alt((
                 value(DisplayKind::Inline, tag("inline")),
                 value(DisplayKind::Block, tag("block")),
+                value(DisplayKind::ListItem, tag("list-item")),
                 value(DisplayKind::None, tag("none")),
             )),
             Value::Display,
@@ -113,6 +435,180 @@ fn display_kind(input: &str) -> IResult<&str, Value> {
     )(input)
 }
 
+fn white_space(input: &str) -> IResult<&str, Value> {
+    context(
+        "white-space",
+        map(
+            alt((
+                value(WhiteSpace::Normal, tag("normal")),
+                // `pre-wrap` and `pre-line` must be tried before `pre`.
+                value(WhiteSpace::PreWrap, tag("pre-wrap")),
+                value(WhiteSpace::PreLine, tag("pre-line")),
+                value(WhiteSpace::Pre, tag("pre")),
+                value(WhiteSpace::NoWrap, tag("nowrap")),
+            )),
+            Value::WhiteSpace,
+        ),
+    )(input)
+}
+
+/// The `content` of a pseudo-element: `none`, `normal`, or a list of strings,
+/// `attr()`s and counters.
+fn content(input: &str) -> IResult<&str, Value> {
+    context(
+        "content",
+        alt((
+            value(Value::Keyword(String::from("none")), tag("none")),
+            value(Value::Keyword(String::from("normal")), tag("normal")),
+            map(separated_list1(space1, content_item), Value::Content),
+        )),
+    )(input)
+}
+
+fn content_item(input: &str) -> IResult<&str, ContentItem> {
+    alt((
+        map(string, ContentItem::Text),
+        map(function("attr", identifier), |n| {
+            ContentItem::Attr(String::from(n))
+        }),
+        map(
+            function("counter", pair(identifier, opt(comma(counter_style)))),
+            |(n, style)| {
+                ContentItem::Counter(String::from(n), style.unwrap_or(ListStyleType::Decimal))
+            },
+        ),
+        map(
+            function(
+                "counters",
+                tuple((identifier, comma(string), opt(comma(counter_style)))),
+            ),
+            |(n, separator, style)| {
+                ContentItem::Counters(
+                    String::from(n),
+                    separator,
+                    style.unwrap_or(ListStyleType::Decimal),
+                )
+            },
+        ),
+    ))(input)
+}
+
+/// Another argument of a function, after a comma.
+fn comma<'a, T>(
+    parser: impl FnMut(&'a str) -> IResult<&str, T>,
+) -> impl FnMut(&'a str) -> IResult<&str, T> {
+    preceded(skip_ws(char(',')), parser)
+}
+
+/// The style a counter is written in.
+fn counter_style(input: &str) -> IResult<&str, ListStyleType> {
+    context(
+        "counter style",
+        alt((
+            value(ListStyleType::None, tag("none")),
+            value(ListStyleType::Disc, tag("disc")),
+            value(ListStyleType::Circle, tag("circle")),
+            value(ListStyleType::Square, tag("square")),
+            value(ListStyleType::Decimal, tag("decimal")),
+            value(ListStyleType::LowerAlpha, tag("lower-alpha")),
+            value(ListStyleType::LowerRoman, tag("lower-roman")),
+        )),
+    )(input)
+}
+
+fn list_style_type(input: &str) -> IResult<&str, Value> {
+    context(
+        "list-style-type",
+        map(
+            alt((counter_style, map(string, ListStyleType::String))),
+            Value::ListStyleType,
+        ),
+    )(input)
+}
+
+fn list_style_position(input: &str) -> IResult<&str, Value> {
+    context(
+        "list-style-position",
+        map(
+            alt((
+                value(ListStylePosition::Outside, tag("outside")),
+                value(ListStylePosition::Inside, tag("inside")),
+            )),
+            Value::ListStylePosition,
+        ),
+    )(input)
+}
+
+/// The counters of `counter-reset` or `counter-increment`, each optionally
+/// followed by a value, or `none`.
+fn counter_list<'a>(default: i32) -> impl FnMut(&'a str) -> IResult<&'a str, Value> {
+    context(
+        "counters",
+        map(
+            alt((
+                value(Vec::new(), tag("none")),
+                separated_list1(
+                    space1,
+                    map(
+                        pair(identifier, opt(preceded(space1, decimal))),
+                        move |(name, value)| (String::from(name), value.unwrap_or(default)),
+                    ),
+                ),
+            )),
+            Value::Counters,
+        ),
+    )
+}
+
+/// A quoted string, in which a backslash escapes the next character.
+fn string(input: &str) -> IResult<&str, String> {
+    let quoted = |quote: char| {
+        delimited(
+            char(quote),
+            many0(alt((
+                preceded(char('\\'), anychar),
+                satisfy(move |c| c != quote && c != '\\'),
+            ))),
+            char(quote),
+        )
+    };
+    context(
+        "string",
+        map(alt((quoted('"'), quoted('\''))), |chars| {
+            chars.into_iter().collect()
+        }),
+    )(input)
+}
+
+fn text_align(input: &str) -> IResult<&str, Value> {
+    context(
+        "text-align",
+        map(
+            alt((
+                value(TextAlign::Left, tag("left")),
+                value(TextAlign::Right, tag("right")),
+                value(TextAlign::Center, tag("center")),
+                value(TextAlign::Justify, tag("justify")),
+            )),
+            Value::TextAlign,
+        ),
+    )(input)
+}
+
+fn align_content(input: &str) -> IResult<&str, Value> {
+    context(
+        "align-content",
+        map(
+            alt((
+                value(AlignContent::Start, tag("start")),
+                value(AlignContent::Center, tag("center")),
+                value(AlignContent::End, tag("end")),
+            )),
+            Value::AlignContent,
+        ),
+    )(input)
+}
+
 fn abs_len(input: &str) -> IResult<&str, Value> {
     context("absolute length", map(decimal, Value::AbsoluteLength))(input)
 }
@@ -130,6 +626,21 @@ fn decimal(input: &str) -> IResult<&str, i32> {
     )(input)
 }
 
+/// A color, as a name or a `#` followed by hex digits. It's kept as a
+/// keyword.
+fn color(input: &str) -> IResult<&str, Value> {
+    context(
+        "color",
+        map(
+            alt((
+                recognize(pair(char('#'), hex_digit1)),
+                verify(identifier, |name: &str| border(name).is_err()),
+            )),
+            |color| Value::Keyword(String::from(color)),
+        ),
+    )(input)
+}
+
 fn border(input: &str) -> IResult<&str, Value> {
     context(
         "border",
@@ -147,8 +658,49 @@ fn border(input: &str) -> IResult<&str, Value> {
 
 // == selector ============================================================
 
+/// A selector on its own, as passed to `query_selector`.
+pub(super) fn selector(input: &str) -> IResult<&str, Selector> {
+    terminated(skip_ws(any_selector), context("end of selector", eof))(input)
+}
+
+/// A compound selector, optionally followed by a pseudo-element.
 fn any_selector(input: &str) -> IResult<&str, Selector> {
-    alt((class, id))(input)
+    alt((
+        map(
+            pair(compound_selector, opt(pseudo_element)),
+            |(selector, pseudo_element)| Selector {
+                pseudo_element,
+                ..selector
+            },
+        ),
+        map(pseudo_element, |pseudo_element| Selector {
+            id: None,
+            classes: Vec::new(),
+            pseudo_classes: Vec::new(),
+            pseudo_element: Some(pseudo_element),
+        }),
+    ))(input)
+}
+
+/// A compound selector, such as `.a#b:first-child`, which matches elements
+/// that match every part of it.
+fn compound_selector(input: &str) -> IResult<&str, Selector> {
+    map(many1(alt((class, id, pseudo_class))), |parts| {
+        parts.into_iter().fold(
+            Selector {
+                id: None,
+                classes: Vec::new(),
+                pseudo_classes: Vec::new(),
+                pseudo_element: None,
+            },
+            |mut selector, part| {
+                selector.id = selector.id.or(part.id);
+                selector.classes.extend(part.classes);
+                selector.pseudo_classes.extend(part.pseudo_classes);
+                selector
+            },
+        )
+    })(input)
 }
 
 fn class(input: &str) -> IResult<&str, Selector> {
@@ -157,6 +709,8 @@ fn class(input: &str) -> IResult<&str, Selector> {
         map(preceded(tag("."), identifier), |class| Selector {
             id: None,
             classes: vec![String::from(class)],
+            pseudo_classes: Vec::new(),
+            pseudo_element: None,
         }),
     )(input)
 }
@@ -167,10 +721,93 @@ fn id(input: &str) -> IResult<&str, Selector> {
         map(preceded(tag("#"), identifier), |id| Selector {
             id: Some(String::from(id)),
             classes: Vec::new(),
+            pseudo_classes: Vec::new(),
+            pseudo_element: None,
         }),
     )(input)
 }
 
+fn pseudo_class(input: &str) -> IResult<&str, Selector> {
+    context(
+        "pseudo-class",
+        map(
+            preceded(
+                char(':'),
+                alt((
+                    map(function("nth-child", nth), PseudoClass::NthChild),
+                    map(function("nth-last-child", nth), PseudoClass::NthLastChild),
+                    map(function("not", compound_selector), |s| {
+                        PseudoClass::Not(Box::new(s))
+                    }),
+                    value(PseudoClass::FirstChild, tag("first-child")),
+                    value(PseudoClass::LastChild, tag("last-child")),
+                    value(PseudoClass::Empty, tag("empty")),
+                )),
+            ),
+            |pseudo_class| Selector {
+                id: None,
+                classes: Vec::new(),
+                pseudo_classes: vec![pseudo_class],
+                pseudo_element: None,
+            },
+        ),
+    )(input)
+}
+
+fn pseudo_element(input: &str) -> IResult<&str, PseudoElement> {
+    context(
+        "pseudo-element",
+        preceded(
+            tag("::"),
+            alt((
+                value(PseudoElement::Before, tag("before")),
+                value(PseudoElement::After, tag("after")),
+            )),
+        ),
+    )(input)
+}
+
+/// A function such as `not(.a)` or `attr(title)`, whose argument `parser`
+/// parses.
+fn function<'a, T>(
+    name: &'static str,
+    parser: impl FnMut(&'a str) -> IResult<&str, T>,
+) -> impl FnMut(&'a str) -> IResult<&str, T> {
+    delimited(pair(tag(name), char('(')), skip_ws(parser), char(')'))
+}
+
+/// The argument of `:nth-child()`: `odd`, `even`, or `an+b` with either part
+/// left out.
+fn nth(input: &str) -> IResult<&str, Nth> {
+    let coefficient = alt((decimal, value(1, char('+')), value(-1, char('-'))));
+    // Offsets whose negation doesn't fit in an `i32` are rejected.
+    let offset = map_opt(
+        pair(delimited(multispace0, one_of("+-"), multispace0), decimal),
+        |(sign, b)| {
+            if sign == '-' {
+                b.checked_neg()
+            } else {
+                Some(b)
+            }
+        },
+    );
+    context(
+        "an+b",
+        alt((
+            value(Nth { a: 2, b: 1 }, tag("odd")),
+            value(Nth { a: 2, b: 0 }, tag("even")),
+            map(
+                pair(terminated(opt(coefficient), char('n')), opt(offset)),
+                |(a, b)| Nth {
+                    a: a.unwrap_or(1),
+                    b: b.unwrap_or(0),
+                },
+            ),
+            map(decimal, |b| Nth { a: 0, b }),
+        )),
+    )(input)
+}
+
 #[tracing::instrument(level = "trace", err)]
 fn identifier(input: &str) -> IResult<&str, &str> {
     context(
@@ -179,6 +816,13 @@ fn identifier(input: &str) -> IResult<&str, &str> {
     )(input)
 }
 
+fn custom_property(input: &str) -> IResult<&str, &str> {
+    context(
+        "custom property",
+        recognize(preceded(tag("--"), identifier)),
+    )(input)
+}
+
 fn skip_ws<'a, T>(
     parser: impl FnMut(&'a str) -> IResult<&str, T>,
 ) -> impl FnMut(&'a str) -> IResult<&str, T> {
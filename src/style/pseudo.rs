@@ -0,0 +1,280 @@
+//! Structural pseudo-classes, such as `:first-child`, and the information
+//! about an element's place in the tree that they need, along with the
+//! `::before` and `::after` pseudo-elements.
+
+use super::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PseudoClass {
+    FirstChild,
+    LastChild,
+    NthChild(Nth),
+    NthLastChild(Nth),
+    /// Matches elements without any children, including text.
+    Empty,
+    Not(Box<Selector>),
+}
+
+/// A box generated for an element without a node of its own, which is styled
+/// by selectors ending in `::before` or `::after`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PseudoElement {
+    /// Generated content placed before the element's first child.
+    Before,
+    /// Generated content placed after the element's last child.
+    After,
+}
+
+impl PseudoElement {
+    pub const ALL: [PseudoElement; 2] = [PseudoElement::Before, PseudoElement::After];
+}
+
+/// An `an+b` pattern, matching the positions `a*n + b` for every `n` from 0
+/// up, counting from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nth {
+    pub a: i32,
+    pub b: i32,
+}
+
+impl Nth {
+    pub fn matches(&self, position: usize) -> bool {
+        // Any `a` and `b` fit without overflowing in an `i64`.
+        let offset = position as i64 - i64::from(self.b);
+        match i64::from(self.a) {
+            0 => offset == 0,
+            a => offset % a == 0 && offset / a >= 0,
+        }
+    }
+}
+
+/// An element to match a selector against, along with where it is among its
+/// siblings.
+///
+/// Positions count only element siblings, not text. The root element is the
+/// only child of its parent.
+#[derive(Debug, Clone, Copy)]
+pub struct Subject<'a> {
+    pub element: &'a ElementData,
+    /// The element's position among its parent's element children, counting
+    /// from 1.
+    pub position: usize,
+    /// How many element children its parent has.
+    pub siblings: usize,
+    pub empty: bool,
+    /// The pseudo-element of the element being styled, if any. Only
+    /// selectors that end in the same pseudo-element match it.
+    pub pseudo_element: Option<PseudoElement>,
+}
+
+impl<'a> Subject<'a> {
+    /// The node in `document` with id `node`, if it is an element.
+    pub fn in_document(document: &'a Document, node: NodeId) -> Option<Subject<'a>> {
+        let element = document[node].element_data()?;
+        let (position, siblings) = match document[node].parent() {
+            Some(parent) => {
+                let siblings = document[parent].children();
+                let index = siblings.iter().position(|&s| s == node)?;
+                position(
+                    siblings
+                        .iter()
+                        .map(|&s| document[s].element_data().is_some()),
+                    index,
+                )
+            }
+            None => (1, 1),
+        };
+        Some(Subject {
+            element,
+            position,
+            siblings,
+            empty: document[node].children().is_empty(),
+            pseudo_element: None,
+        })
+    }
+
+    /// The node under `cursor`, if it is an element.
+    pub fn at(cursor: &Cursor<'a>) -> Option<Subject<'a>> {
+        let node = cursor.node();
+        let element = node.element_data()?;
+        let (position, siblings) = match (cursor.parent(), cursor.path().last()) {
+            (Some(parent), Some(&index)) => position(
+                parent.node().children().map(|s| s.element_data().is_some()),
+                index,
+            ),
+            _ => (1, 1),
+        };
+        Some(Subject {
+            element,
+            position,
+            siblings,
+            empty: node.children().next().is_none(),
+            pseudo_element: None,
+        })
+    }
+
+    /// The same element's `pseudo_element`.
+    pub fn with_pseudo_element(self, pseudo_element: PseudoElement) -> Subject<'a> {
+        Subject {
+            pseudo_element: Some(pseudo_element),
+            ..self
+        }
+    }
+
+    /// The element's position counting back from its last sibling, from 1.
+    pub fn position_from_end(&self) -> usize {
+        self.siblings + 1 - self.position
+    }
+}
+
+/// The position, from 1, of the child at `index` among the children that are
+/// elements, and how many children are elements.
+fn position(elements: impl Iterator<Item = bool>, index: usize) -> (usize, usize) {
+    let mut position = 0;
+    let mut count = 0;
+    for (i, element) in elements.enumerate() {
+        if element {
+            count += 1;
+        }
+        if i == index {
+            position = count;
+        }
+    }
+    (position, count)
+}
+
+impl PseudoClass {
+    pub fn matches(&self, subject: &Subject) -> bool {
+        match self {
+            PseudoClass::FirstChild => subject.position == 1,
+            PseudoClass::LastChild => subject.position_from_end() == 1,
+            PseudoClass::NthChild(nth) => nth.matches(subject.position),
+            PseudoClass::NthLastChild(nth) => nth.matches(subject.position_from_end()),
+            PseudoClass::Empty => subject.empty,
+            // The argument is matched against the element itself, even when
+            // styling one of its pseudo-elements.
+            PseudoClass::Not(selector) => !selector.matches(&Subject {
+                pseudo_element: None,
+                ..*subject
+            }),
+        }
+    }
+
+    /// What the pseudo-class adds to a selector's specificity: as much as a
+    /// class, except for `:not()`, which adds its argument's.
+    pub fn specificity(&self) -> Specificity {
+        match self {
+            PseudoClass::Not(selector) => selector.specificity(),
+            _ => (0, 1, 0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn selector(s: &str) -> Selector {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_and_specificity() {
+        let nth = |s: &str| match &selector(&format!(":nth-child({})", s)).pseudo_classes[..] {
+            [PseudoClass::NthChild(nth)] => (nth.a, nth.b),
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(nth("odd"), (2, 1));
+        assert_eq!(nth("even"), (2, 0));
+        assert_eq!(nth("3n+1"), (3, 1));
+        assert_eq!(nth(" -n + 3 "), (-1, 3));
+        assert_eq!(nth("+n-2"), (1, -2));
+        assert_eq!(nth("n"), (1, 0));
+        assert_eq!(nth("4"), (0, 4));
+        assert!(":nth-child(n+)".parse::<Selector>().is_err());
+        assert_eq!(nth("n-2147483647"), (1, -2147483647));
+        assert!(":nth-child(n - -2147483648)".parse::<Selector>().is_err());
+        assert!(":nth-child(2147483648n)".parse::<Selector>().is_err());
+
+        assert_eq!(
+            selector(".li:not(#x.a):first-child"),
+            Selector {
+                id: None,
+                classes: vec!["li".to_string()],
+                pseudo_classes: vec![
+                    PseudoClass::Not(Box::new(selector("#x.a"))),
+                    PseudoClass::FirstChild,
+                ],
+                pseudo_element: None,
+            }
+        );
+        // `:not()` counts as its argument, other pseudo-classes as classes.
+        assert_eq!(
+            selector(".li:not(#x.a):first-child").specificity(),
+            (1, 3, 0)
+        );
+        assert_eq!(
+            selector(".li:nth-last-child(2):empty").specificity(),
+            (0, 3, 0)
+        );
+        assert_eq!(selector(".li:first-child::before").specificity(), (0, 2, 1));
+        assert_eq!(
+            selector("::after").pseudo_element,
+            Some(PseudoElement::After)
+        );
+        assert!(".a::before.b".parse::<Selector>().is_err());
+        assert!(".a:not(::before)".parse::<Selector>().is_err());
+    }
+
+    #[test]
+    fn nth() {
+        let positions = |a, b| {
+            (1..=10)
+                .filter(|&i| Nth { a, b }.matches(i))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(positions(2, 1), vec![1, 3, 5, 7, 9]);
+        assert_eq!(positions(3, -1), vec![2, 5, 8]);
+        assert_eq!(positions(-1, 3), vec![1, 2, 3]);
+        assert_eq!(positions(0, 4), vec![4]);
+        assert_eq!(positions(-2, 0), Vec::<usize>::new());
+        assert_eq!(positions(1, -i32::MAX), (1..=10).collect::<Vec<_>>());
+        assert_eq!(positions(i32::MIN, i32::MAX), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn structural_matching() {
+        let node = "<div><ul><li>a</li>text<li>b</li><li></li><li>d</li></ul></div>"
+            .parse::<Node>()
+            .unwrap();
+        let text = |s: &str| {
+            node.query_selector_all(&selector(s))
+                .into_iter()
+                .map(|n| n.descendants().filter_map(Node::text).collect::<String>())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(text(".li:first-child"), vec!["a"]);
+        assert_eq!(text(".li:last-child"), vec!["d"]);
+        assert_eq!(text(".li:nth-child(even)"), vec!["b", "d"]);
+        assert_eq!(text(".li:nth-last-child(-n+2)"), vec!["", "d"]);
+        assert_eq!(text(".li:not(:empty):not(:first-child)"), vec!["b", "d"]);
+        assert_eq!(text(".ul:first-child:last-child"), vec!["atextbd"]);
+
+        // Styling sees the same positions.
+        let document = Document::new(node.clone());
+        let styles = crate::style_tree::style_tree(
+            &document,
+            &".li:nth-child(odd) { width: 1; }"
+                .parse::<Stylesheet>()
+                .unwrap(),
+        );
+        let widths = document
+            .descendants(document.root())
+            .filter(|&n| document[n].element_data().is_some())
+            .map(|n| styles[n].value("width"))
+            .collect::<Vec<_>>();
+        let one = Some(Value::AbsoluteLength(1));
+        assert_eq!(widths, vec![None, one.clone(), None, one, None]);
+    }
+}
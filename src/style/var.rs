@@ -0,0 +1,226 @@
+//! Custom properties and `var()` substitution.
+//!
+//! Custom properties, named `--like-this`, hold their value as text and are
+//! inherited. Any declaration can refer to one with `var(--name)` or
+//! `var(--name, fallback)`; such values are kept as [`Value::Unresolved`]
+//! until the element is styled, and then substituted and parsed.
+
+use super::*;
+use std::collections::HashMap;
+
+/// Whether `name` is the name of a custom property.
+pub(crate) fn is_custom_property(name: &str) -> bool {
+    name.starts_with("--")
+}
+
+/// Substitute custom properties into every unresolved value in `values`.
+///
+/// Custom properties that refer to each other in a cycle, or to ones that
+/// don't exist without a fallback, are invalid and removed. Other properties
+/// whose values are invalid once substituted fall back to the `inherited`
+/// value, if there is one.
+pub(crate) fn resolve_variables(
+    values: &mut HashMap<String, Value>,
+    inherited: &HashMap<String, Value>,
+) {
+    let mut resolver = Resolver {
+        values,
+        resolved: HashMap::new(),
+        stack: Vec::new(),
+    };
+    for name in values.keys().filter(|name| is_custom_property(name)) {
+        resolver.custom(name);
+    }
+    let resolved = resolver.resolved;
+
+    for (name, value) in resolved {
+        match value {
+            Some(value) => values.insert(name, Value::Keyword(value)),
+            None => values.remove(&name),
+        };
+    }
+    let unresolved = values
+        .iter()
+        .filter_map(|(name, value)| match value {
+            Value::Unresolved { text, shorthand } => {
+                let written = shorthand.clone().unwrap_or_else(|| name.clone());
+                Some((name.clone(), written, text.clone()))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    for (name, written, text) in unresolved {
+        let lookup = &mut |n: &str| match values.get(n) {
+            Some(Value::Keyword(k)) if is_custom_property(n) => Some(k.clone()),
+            _ => None,
+        };
+        let value = substitute(&text, lookup)
+            .and_then(|text| parse::declaration_value(&written, &name, &text));
+        match value {
+            Some(value) => values.insert(name, value),
+            None => match inherited.get(&name) {
+                Some(value) => values.insert(name, value.clone()),
+                None => values.remove(&name),
+            },
+        };
+    }
+}
+
+struct Resolver<'a> {
+    values: &'a HashMap<String, Value>,
+    /// The substituted text of each custom property resolved so far, or
+    /// `None` if it is invalid.
+    resolved: HashMap<String, Option<String>>,
+    /// The custom properties being resolved, innermost last.
+    stack: Vec<String>,
+}
+
+impl Resolver<'_> {
+    fn custom(&mut self, name: &str) -> Option<String> {
+        if let Some(value) = self.resolved.get(name) {
+            return value.clone();
+        }
+        if let Some(start) = self.stack.iter().position(|n| n == name) {
+            // Every property in a cycle is invalid, whatever fallbacks it
+            // has.
+            for n in self.stack.drain(start..).collect::<Vec<_>>() {
+                self.resolved.insert(n, None);
+            }
+            return None;
+        }
+        let value = match self.values.get(name)? {
+            Value::Unresolved { text, .. } => {
+                let text = text.clone();
+                self.stack.push(name.to_string());
+                let value = substitute(&text, &mut |n| self.custom(n));
+                if self.stack.last().map(String::as_str) == Some(name) {
+                    self.stack.pop();
+                }
+                value
+            }
+            Value::Keyword(k) => Some(k.clone()),
+            _ => None,
+        };
+        self.resolved
+            .entry(name.to_string())
+            .or_insert(value)
+            .clone()
+    }
+}
+
+/// Replace each `var(--name)` or `var(--name, fallback)` in `text` with the
+/// value `lookup` gives for it, or its fallback.
+///
+/// Returns `None` if a property has neither a value nor a fallback, or a
+/// `var()` isn't closed.
+fn substitute(text: &str, lookup: &mut dyn FnMut(&str) -> Option<String>) -> Option<String> {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("var(") {
+        out.push_str(&rest[..start]);
+        let arguments = &rest[start + "var(".len()..];
+        let end = closing_paren(arguments)?;
+        let (name, fallback) = match top_level_comma(&arguments[..end]) {
+            Some(comma) => (&arguments[..comma], Some(&arguments[comma + 1..end])),
+            None => (&arguments[..end], None),
+        };
+        let value = match lookup(name.trim()) {
+            Some(value) => value,
+            None => substitute(fallback?.trim(), lookup)?,
+        };
+        out.push_str(&value);
+        rest = &arguments[end + 1..];
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+/// The index of the `)` that closes a parenthesis opened just before `text`.
+fn closing_paren(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(i),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn top_level_comma(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn resolve(tss: &str, inherited: &[(&str, Value)]) -> HashMap<String, Value> {
+        let stylesheet = tss.parse::<Stylesheet>().unwrap();
+        let mut values = stylesheet.rules()[0]
+            .declarations()
+            .iter()
+            .map(|d| (d.name().to_string(), d.value().clone()))
+            .collect::<HashMap<_, _>>();
+        let inherited = inherited
+            .iter()
+            .map(|(n, v)| (n.to_string(), v.clone()))
+            .collect();
+        resolve_variables(&mut values, &inherited);
+        values
+    }
+
+    #[test]
+    fn substitution() {
+        let values = resolve(
+            ".a { --frame: heavy; --pad: var(--gap, 2); --label: [var(--frame)]; \
+             border: var(--frame); padding: var(--pad); width: var(--missing); \
+             --m: 1 2; margin-left: 9; margin: var(--m); }",
+            &[],
+        );
+        let keyword = |s: &str| Some(Value::Keyword(s.to_string()));
+        assert_eq!(values.get("--frame").cloned(), keyword("heavy"));
+        assert_eq!(values.get("--pad").cloned(), keyword("2"));
+        assert_eq!(values.get("--label").cloned(), keyword("[heavy]"));
+        assert_eq!(
+            values.get("border-left"),
+            Some(&Value::Border(Border::Heavy))
+        );
+        assert_eq!(
+            values.get("padding-bottom"),
+            Some(&Value::AbsoluteLength(2))
+        );
+        assert_eq!(values.get("width"), None);
+        assert_eq!(values.get("margin-top"), Some(&Value::AbsoluteLength(1)));
+        assert_eq!(values.get("margin-left"), Some(&Value::AbsoluteLength(2)));
+    }
+
+    #[test]
+    fn cycles() {
+        let values = resolve(
+            ".a { --a: var(--b, 1); --b: var(--a, 2); --c: var(--c); --d: var(--a, 3); \
+             white-space: var(--a); }",
+            &[("white-space", Value::WhiteSpace(WhiteSpace::Pre))],
+        );
+        assert_eq!(values.get("--a"), None);
+        assert_eq!(values.get("--b"), None);
+        assert_eq!(values.get("--c"), None);
+        assert_eq!(values.get("--d"), Some(&Value::Keyword("3".to_string())));
+        assert_eq!(
+            values.get("white-space"),
+            Some(&Value::WhiteSpace(WhiteSpace::Pre))
+        );
+    }
+}
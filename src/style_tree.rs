@@ -1,48 +1,236 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use super::content_tree::*;
+use super::span::Span;
 use super::style::*;
 
 type PropertyMap = HashMap<String, Value>;
+/// The span of the declaration that each specified value came from.
+type SpanMap = HashMap<String, Span>;
 
-#[derive(Debug)]
-pub struct StyledNode<'a> {
-    pub(super) node: &'a Node,
-    pub(super) specified_values: PropertyMap,
-    pub(super) children: Vec<StyledNode<'a>>,
+/// Properties whose values are passed down from parent to child nodes, along
+/// with all custom properties.
+const INHERITED_PROPERTIES: &[&str] = &[
+    "color",
+    "white-space",
+    "text-align",
+    "list-style-type",
+    "list-style-position",
+];
+
+/// The specified values of every node in a [`Document`], keyed by
+/// [`NodeId`].
+#[derive(Debug, Clone)]
+pub struct StyleTree {
+    root: NodeId,
+    nodes: HashMap<NodeId, StyledNode>,
+    /// The styles of the `::before` and `::after` pseudo-elements that
+    /// generate content.
+    pseudo_elements: HashMap<(NodeId, PseudoElement), StyledNode>,
+    /// The document generation the styles are up to date with.
+    generation: u64,
+    cascade: Cascade,
 }
 
-fn specified_values(element: &ElementData, stylesheet: &Stylesheet) -> PropertyMap {
-    let mut values = PropertyMap::new();
-    let mut rules = stylesheet.matching_rules(element);
+#[derive(Debug, Clone)]
+pub struct StyledNode {
+    pub(super) node: NodeId,
+    pub(super) specified_values: PropertyMap,
+    pub(super) declaration_spans: SpanMap,
+}
 
-    rules.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
-    for (_, rule) in &rules {
-        for declaration in &rule.declarations {
-            values.insert(declaration.name.clone(), declaration.value.clone());
-        }
+fn specified_values(
+    subject: &Subject,
+    cascade: &Cascade,
+    inherited: &(PropertyMap, SpanMap),
+) -> (PropertyMap, SpanMap) {
+    let (mut values, mut spans) = inherited.clone();
+    for declaration in cascade.matching_declarations(subject) {
+        values.insert(declaration.name.clone(), declaration.value.clone());
+        spans.insert(declaration.name.clone(), declaration.span);
     }
+    resolve_variables(&mut values, &inherited.0);
 
-    values
+    (values, spans)
 }
 
-pub fn style_tree<'a>(root_node: &'a Node, style: &'a Stylesheet) -> StyledNode<'a> {
-    StyledNode {
-        node: root_node,
-        specified_values: match &root_node.node_data {
-            NodeData::Element(element) => specified_values(&element, style),
-            NodeData::Text(_) => PropertyMap::new(),
-        },
-        children: root_node
-            .children
+fn inherited_values(values: &PropertyMap, spans: &SpanMap) -> (PropertyMap, SpanMap) {
+    let inherited =
+        |name: &&String| INHERITED_PROPERTIES.contains(&name.as_str()) || is_custom_property(name);
+    (
+        values
+            .iter()
+            .filter(|(name, _)| inherited(name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect(),
+        spans
             .iter()
-            .map(|c| style_tree(c, style))
+            .filter(|(name, _)| inherited(name))
+            .map(|(name, span)| (name.clone(), *span))
             .collect(),
+    )
+}
+
+/// Style a document with a [`Cascade`], or with a single author stylesheet
+/// over the built-in user agent stylesheet.
+pub fn style_tree(document: &Document, style: impl Into<Cascade>) -> StyleTree {
+    let mut tree = StyleTree {
+        root: document.root(),
+        nodes: HashMap::new(),
+        pseudo_elements: HashMap::new(),
+        generation: document.generation(),
+        cascade: style.into(),
+    };
+    tree.style_node(document, document.root(), &Default::default());
+    tree
+}
+
+/// Style a document with `defaults` in place of the built-in user agent
+/// stylesheet. Pass an empty stylesheet to use no defaults at all.
+pub fn style_tree_with_defaults(
+    document: &Document,
+    defaults: &Stylesheet,
+    style: &Stylesheet,
+) -> StyleTree {
+    let cascade = Cascade::new()
+        .with(Origin::UserAgent, defaults.clone())
+        .with(Origin::Author, style.clone());
+    style_tree(document, cascade)
+}
+
+impl StyleTree {
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn get(&self, node: NodeId) -> Option<&StyledNode> {
+        self.nodes.get(&node)
+    }
+
+    /// The style of a pseudo-element of `node`, if it generates content.
+    pub fn pseudo_element(
+        &self,
+        node: NodeId,
+        pseudo_element: PseudoElement,
+    ) -> Option<&StyledNode> {
+        self.pseudo_elements.get(&(node, pseudo_element))
+    }
+
+    /// The stylesheets the tree was styled with.
+    pub fn cascade(&self) -> &Cascade {
+        &self.cascade
+    }
+
+    /// Recompute the styles of the nodes that changed in `document` since it
+    /// was last styled, along with their descendants, which may inherit from
+    /// them.
+    ///
+    /// Returns the restyled nodes in document order.
+    pub fn restyle(&mut self, document: &Document) -> Vec<NodeId> {
+        let mut restyled = Vec::new();
+        let mut done = HashSet::new();
+        for node in document.changed_since(self.generation) {
+            // Nodes come in document order, so a changed ancestor has
+            // already restyled this node.
+            if done.contains(&node) {
+                continue;
+            }
+            let inherited = match document[node].parent() {
+                Some(parent) => {
+                    let parent = &self[parent];
+                    inherited_values(&parent.specified_values, &parent.declaration_spans)
+                }
+                None => Default::default(),
+            };
+            self.style_node(document, node, &inherited);
+            for node in std::iter::once(node).chain(document.descendants(node)) {
+                done.insert(node);
+                restyled.push(node);
+            }
+        }
+        self.generation = document.generation();
+        restyled
+    }
+
+    /// Change the viewport that `@media` queries are tested against, and
+    /// restyle the document if that changes which rules apply.
+    ///
+    /// Returns the restyled nodes in document order.
+    pub fn resize(&mut self, document: &Document, viewport: Viewport) -> Vec<NodeId> {
+        if !self.cascade.set_viewport(viewport) {
+            return Vec::new();
+        }
+        self.style_node(document, self.root, &Default::default());
+        std::iter::once(self.root)
+            .chain(document.descendants(self.root))
+            .collect()
+    }
+
+    fn style_node(
+        &mut self,
+        document: &Document,
+        node: NodeId,
+        inherited: &(PropertyMap, SpanMap),
+    ) {
+        let subject = Subject::in_document(document, node);
+        let (specified_values, declaration_spans) = match &subject {
+            Some(subject) => specified_values(subject, &self.cascade, inherited),
+            None => inherited.clone(),
+        };
+        let inherited = inherited_values(&specified_values, &declaration_spans);
+        self.style_pseudo_elements(node, subject.as_ref(), &inherited);
+        self.nodes.insert(
+            node,
+            StyledNode {
+                node,
+                specified_values,
+                declaration_spans,
+            },
+        );
+        for &child in document[node].children() {
+            self.style_node(document, child, &inherited);
+        }
+    }
+
+    /// Style the pseudo-elements of `node`, which inherit from it.
+    fn style_pseudo_elements(
+        &mut self,
+        node: NodeId,
+        subject: Option<&Subject>,
+        inherited: &(PropertyMap, SpanMap),
+    ) {
+        for &pseudo_element in &PseudoElement::ALL {
+            self.pseudo_elements.remove(&(node, pseudo_element));
+            let subject = match subject {
+                Some(subject) => subject.with_pseudo_element(pseudo_element),
+                None => continue,
+            };
+            let (values, spans) = specified_values(&subject, &self.cascade, inherited);
+            // Pseudo-elements only generate boxes when they have content.
+            if let Some(Value::Content(_)) = values.get("content") {
+                self.pseudo_elements.insert(
+                    (node, pseudo_element),
+                    StyledNode {
+                        node,
+                        specified_values: values,
+                        declaration_spans: spans,
+                    },
+                );
+            }
+        }
     }
 }
 
-impl<'a> StyledNode<'a> {
-    pub fn node(&self) -> &'a Node {
+impl std::ops::Index<NodeId> for StyleTree {
+    type Output = StyledNode;
+
+    fn index(&self, node: NodeId) -> &StyledNode {
+        &self.nodes[&node]
+    }
+}
+
+impl StyledNode {
+    pub fn node(&self) -> NodeId {
         self.node
     }
 
@@ -55,15 +243,80 @@ impl<'a> StyledNode<'a> {
         }
     }
 
+    pub fn white_space(&self) -> WhiteSpace {
+        match self.value("white-space") {
+            Some(Value::WhiteSpace(w)) => w,
+            _ => WhiteSpace::Normal,
+        }
+    }
+
+    pub fn text_align(&self) -> TextAlign {
+        match self.value("text-align") {
+            Some(Value::TextAlign(a)) => a,
+            _ => TextAlign::Left,
+        }
+    }
+
+    pub fn align_content(&self) -> AlignContent {
+        match self.value("align-content") {
+            Some(Value::AlignContent(a)) => a,
+            _ => AlignContent::Start,
+        }
+    }
+
+    /// The color of the node's text, if it has one.
+    pub fn color(&self) -> Option<Color> {
+        match self.value("color") {
+            Some(Value::Keyword(k)) => Color::parse(&k),
+            _ => None,
+        }
+    }
+
+    /// The color of one of the node's borders, `border-top-color` for
+    /// example, which is the text color unless it is set.
+    pub fn border_color(&self, property: &str) -> Option<Color> {
+        match self.value(property) {
+            Some(Value::Keyword(k)) => Color::parse(&k),
+            _ => self.color(),
+        }
+    }
+
+    pub fn list_style_type(&self) -> ListStyleType {
+        match self.value("list-style-type") {
+            Some(Value::ListStyleType(t)) => t,
+            _ => ListStyleType::Disc,
+        }
+    }
+
+    pub fn list_style_position(&self) -> ListStylePosition {
+        match self.value("list-style-position") {
+            Some(Value::ListStylePosition(p)) => p,
+            _ => ListStylePosition::Outside,
+        }
+    }
+
+    /// The counters named by `property`, `counter-reset` or
+    /// `counter-increment`, and their values.
+    pub fn counters(&self, property: &str) -> Vec<(String, i32)> {
+        match self.value(property) {
+            Some(Value::Counters(counters)) => counters,
+            _ => Vec::new(),
+        }
+    }
+
     pub fn value(&self, keyword: &str) -> Option<Value> {
-        self.specified_values.get(keyword).map(|v| v.clone())
+        self.specified_values.get(keyword).cloned()
+    }
+
+    /// Where the declaration that set `keyword` on this node was written,
+    /// if it was parsed from a stylesheet.
+    pub fn declaration_span(&self, keyword: &str) -> Option<Span> {
+        self.declaration_spans.get(keyword).copied()
     }
 
-    pub fn lookup(&self, keyword: &str, shorthand: &str, default: &Value) -> Value {
-        self.specified_values
-            .get(keyword)
-            .or_else(|| self.specified_values.get(shorthand))
-            .map(|v| v.clone())
-            .unwrap_or(default.clone())
+    /// The value of `keyword`, or `default` if it isn't set. Shorthands
+    /// are expanded when they are parsed, so `keyword` should be a longhand.
+    pub fn lookup(&self, keyword: &str, default: &Value) -> Value {
+        self.value(keyword).unwrap_or_else(|| default.clone())
     }
 }